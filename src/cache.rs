@@ -0,0 +1,325 @@
+//! Upload cache functions.
+//!
+//! The cache maps the hash of an upload's contents, filename and the options that change its
+//! skylink to the skylink returned for it, and is kept in offchain storage.
+
+use crate::crypto::{hash_all, HASH_LENGTH};
+use crate::encoding::{encode_number, encode_str};
+use crate::upload::UploadOptions;
+use crate::util::concat_bytes;
+
+use sp_core::offchain::StorageKind;
+use sp_io::offchain;
+use sp_runtime::offchain::storage_lock::{StorageLock, Time};
+use sp_std::prelude::Vec;
+
+/// The key, relative to the prefix, under which the hashes of the cached uploads are stored, oldest
+/// first.
+const INDEX_KEY: &[u8] = b"index";
+
+/// The key, relative to the prefix, of the lock held while the index is updated.
+const LOCK_KEY: &[u8] = b"lock";
+
+/// Upload cache options.
+#[derive(Debug)]
+pub struct UploadCacheOptions<'a> {
+    /// The prefix of the offchain storage keys used by the cache.
+    pub key_prefix: &'a [u8],
+    /// The maximum number of cached skylinks. The oldest skylinks are evicted first.
+    pub max_entries: u32,
+}
+
+impl Default for UploadCacheOptions<'_> {
+    fn default() -> Self {
+        Self {
+            key_prefix: b"skynet-substrate::upload-cache::",
+            max_entries: 128,
+        }
+    }
+}
+
+/// Hashes the contents, filename and options of an upload. The contents are hashed on their own
+/// first so that they don't have to be copied.
+pub fn hash_upload(bytes: &[u8], filename: &str, opts: &UploadOptions) -> Vec<u8> {
+    hash_all(&[
+        &sp_core_hashing::blake2_256(bytes),
+        &encode_str(filename),
+        &encode_upload_options(opts),
+    ])
+}
+
+/// Encodes the upload options that end up in the skyfile metadata, and so change the skylink, along
+/// with the portal uploaded to, as a skylink cached for one portal isn't stored on another.
+fn encode_upload_options(opts: &UploadOptions) -> Vec<u8> {
+    fn encode_option_str(encoded: &mut Vec<u8>, s: Option<&str>) {
+        match s {
+            Some(s) => {
+                encoded.push(1);
+                encoded.extend_from_slice(&encode_str(s));
+            }
+            None => encoded.push(0),
+        }
+    }
+
+    let mut encoded = encode_str(opts.common.portal_url);
+    encoded.extend_from_slice(&encode_str(opts.endpoint_upload));
+    encode_option_str(&mut encoded, opts.content_type);
+    match opts.try_files {
+        Some(try_files) => {
+            encoded.push(1);
+            encoded.extend_from_slice(&encode_number(try_files.len() as u64));
+            for file in try_files {
+                encoded.extend_from_slice(&encode_str(file));
+            }
+        }
+        None => encoded.push(0),
+    }
+    match opts.error_pages {
+        Some(error_pages) => {
+            encoded.push(1);
+            encoded.extend_from_slice(&encode_number(error_pages.len() as u64));
+            for (code, path) in error_pages {
+                encoded.extend_from_slice(&encode_number(*code as u64));
+                encoded.extend_from_slice(&encode_str(path));
+            }
+        }
+        None => encoded.push(0),
+    }
+    encode_option_str(&mut encoded, opts.default_path);
+    encoded.push(opts.disable_default_path as u8);
+
+    encoded
+}
+
+/// Gets the cached skylink for the upload with the given `hash`.
+pub fn get_cached_skylink(hash: &[u8], opts: &UploadCacheOptions) -> Option<Vec<u8>> {
+    offchain::local_storage_get(StorageKind::PERSISTENT, &entry_key(hash, opts))
+}
+
+/// Caches the `skylink` for the upload with the given `hash`, evicting the oldest skylinks if the
+/// cache is full.
+pub fn cache_skylink(hash: &[u8], skylink: &[u8], opts: &UploadCacheOptions) {
+    let lock_key = lock_key(opts);
+    let mut lock = StorageLock::<Time>::new(&lock_key);
+    let _guard = lock.lock();
+
+    let mut index = get_index(opts);
+    if !index.chunks(HASH_LENGTH).any(|h| h == hash) {
+        index.extend_from_slice(hash);
+    }
+
+    // Evict the oldest entries.
+    let max_len = opts.max_entries as usize * HASH_LENGTH;
+    if index.len() > max_len {
+        let evicted = index.drain(..index.len() - max_len).collect::<Vec<_>>();
+        for evicted_hash in evicted.chunks(HASH_LENGTH) {
+            offchain::local_storage_clear(StorageKind::PERSISTENT, &entry_key(evicted_hash, opts));
+        }
+    }
+
+    if opts.max_entries > 0 {
+        offchain::local_storage_set(StorageKind::PERSISTENT, &entry_key(hash, opts), skylink);
+    }
+    set_index(&index, opts);
+}
+
+/// Removes the cached skylink for the upload of `bytes` with `filename` and the upload options
+/// `opts`, so that the next upload is sent to the portal again. The cache options are taken from
+/// `opts`.
+pub fn invalidate_upload_cache(bytes: &[u8], filename: &str, opts: Option<&UploadOptions>) {
+    let default = Default::default();
+    let upload_opts = opts.unwrap_or(&default);
+    let default = Default::default();
+    let opts = upload_opts.cache.unwrap_or(&default);
+
+    let hash = hash_upload(bytes, filename, upload_opts);
+
+    let lock_key = lock_key(opts);
+    let mut lock = StorageLock::<Time>::new(&lock_key);
+    let _guard = lock.lock();
+
+    let index = get_index(opts)
+        .chunks(HASH_LENGTH)
+        .filter(|h| *h != hash.as_slice())
+        .flatten()
+        .copied()
+        .collect::<Vec<_>>();
+    set_index(&index, opts);
+
+    offchain::local_storage_clear(StorageKind::PERSISTENT, &entry_key(&hash, opts));
+}
+
+/// Removes all cached skylinks.
+pub fn clear_upload_cache(opts: Option<&UploadCacheOptions>) {
+    let default = Default::default();
+    let opts = opts.unwrap_or(&default);
+
+    let lock_key = lock_key(opts);
+    let mut lock = StorageLock::<Time>::new(&lock_key);
+    let _guard = lock.lock();
+
+    for hash in get_index(opts).chunks(HASH_LENGTH) {
+        offchain::local_storage_clear(StorageKind::PERSISTENT, &entry_key(hash, opts));
+    }
+    offchain::local_storage_clear(StorageKind::PERSISTENT, &index_key(opts));
+}
+
+fn entry_key(hash: &[u8], opts: &UploadCacheOptions) -> Vec<u8> {
+    concat_bytes(&[opts.key_prefix, hash])
+}
+
+fn index_key(opts: &UploadCacheOptions) -> Vec<u8> {
+    concat_bytes(&[opts.key_prefix, INDEX_KEY])
+}
+
+fn lock_key(opts: &UploadCacheOptions) -> Vec<u8> {
+    concat_bytes(&[opts.key_prefix, LOCK_KEY])
+}
+
+fn get_index(opts: &UploadCacheOptions) -> Vec<u8> {
+    offchain::local_storage_get(StorageKind::PERSISTENT, &index_key(opts)).unwrap_or_default()
+}
+
+fn set_index(index: &[u8], opts: &UploadCacheOptions) {
+    offchain::local_storage_set(StorageKind::PERSISTENT, &index_key(opts), index);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::CommonOptions;
+    use crate::util::str_to_bytes;
+
+    use sp_core::offchain::{testing, OffchainDbExt, OffchainWorkerExt};
+    use sp_io::TestExternalities;
+
+    const SKYLINK_1: &str = "sia://MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ";
+    const SKYLINK_2: &str = "sia://AAA6Z7R0sjreLCr35fJKhMXuc8CE6mxRhkHQtmgtJGzqvw";
+
+    #[test]
+    fn should_hash_contents_filename_and_options() {
+        let opts = UploadOptions::default();
+        assert_eq!(hash_upload(b"foo", "bar", &opts).len(), HASH_LENGTH);
+        assert_eq!(
+            hash_upload(b"foo", "bar", &opts),
+            hash_upload(b"foo", "bar", &opts)
+        );
+        assert_ne!(
+            hash_upload(b"foo", "bar", &opts),
+            hash_upload(b"foo", "baz", &opts)
+        );
+        assert_ne!(
+            hash_upload(b"foo", "bar", &opts),
+            hash_upload(b"fo", "obar", &opts)
+        );
+
+        // Options that change the skylink or the portal storing it change the hash.
+        let changed_opts = [
+            UploadOptions {
+                common: CommonOptions {
+                    portal_url: "https://skyportal.xyz",
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            UploadOptions {
+                content_type: Some("text/plain"),
+                ..Default::default()
+            },
+            UploadOptions {
+                try_files: Some(&["index.html"]),
+                ..Default::default()
+            },
+            UploadOptions {
+                error_pages: Some(&[(404, "/404.html")]),
+                ..Default::default()
+            },
+            UploadOptions {
+                default_path: Some("index.html"),
+                ..Default::default()
+            },
+            UploadOptions {
+                disable_default_path: true,
+                ..Default::default()
+            },
+            UploadOptions {
+                endpoint_upload: "/skynet/skyfile/v2",
+                ..Default::default()
+            },
+        ];
+        for changed_opts in &changed_opts {
+            assert_ne!(
+                hash_upload(b"foo", "bar", &opts),
+                hash_upload(b"foo", "bar", changed_opts)
+            );
+        }
+    }
+
+    #[test]
+    fn should_cache_and_evict_oldest_skylinks() {
+        let (offchain, _state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainWorkerExt::new(offchain.clone()));
+        t.register_extension(OffchainDbExt::new(offchain));
+
+        t.execute_with(|| {
+            let opts = UploadCacheOptions {
+                max_entries: 2,
+                ..Default::default()
+            };
+            let hash_1 = hash_upload(b"1", "file", &Default::default());
+            let hash_2 = hash_upload(b"2", "file", &Default::default());
+            let hash_3 = hash_upload(b"3", "file", &Default::default());
+
+            assert_eq!(get_cached_skylink(&hash_1, &opts), None);
+
+            cache_skylink(&hash_1, SKYLINK_1.as_bytes(), &opts);
+            cache_skylink(&hash_2, SKYLINK_2.as_bytes(), &opts);
+            assert_eq!(
+                get_cached_skylink(&hash_1, &opts),
+                Some(str_to_bytes(SKYLINK_1))
+            );
+            assert_eq!(
+                get_cached_skylink(&hash_2, &opts),
+                Some(str_to_bytes(SKYLINK_2))
+            );
+
+            // The oldest entry should be evicted.
+            cache_skylink(&hash_3, SKYLINK_1.as_bytes(), &opts);
+            assert_eq!(get_cached_skylink(&hash_1, &opts), None);
+            assert_eq!(
+                get_cached_skylink(&hash_2, &opts),
+                Some(str_to_bytes(SKYLINK_2))
+            );
+            assert_eq!(
+                get_cached_skylink(&hash_3, &opts),
+                Some(str_to_bytes(SKYLINK_1))
+            );
+        })
+    }
+
+    #[test]
+    fn should_invalidate_and_clear_cache() {
+        let (offchain, _state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainWorkerExt::new(offchain.clone()));
+        t.register_extension(OffchainDbExt::new(offchain));
+
+        t.execute_with(|| {
+            let opts = Default::default();
+            let hash_1 = hash_upload(b"1", "file", &Default::default());
+            let hash_2 = hash_upload(b"2", "file", &Default::default());
+
+            cache_skylink(&hash_1, SKYLINK_1.as_bytes(), &opts);
+            cache_skylink(&hash_2, SKYLINK_2.as_bytes(), &opts);
+
+            invalidate_upload_cache(b"1", "file", None);
+            assert_eq!(get_cached_skylink(&hash_1, &opts), None);
+            assert_eq!(get_index(&opts), hash_2);
+
+            clear_upload_cache(None);
+            assert_eq!(get_cached_skylink(&hash_2, &opts), None);
+            assert_eq!(get_index(&opts), Vec::<u8>::new());
+        })
+    }
+}
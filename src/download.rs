@@ -1,25 +1,91 @@
 //! Download functions.
 
-use crate::request::{execute_get, CommonOptions, RequestError};
-use crate::util::{make_url, URI_SKYNET_PREFIX};
+use crate::request::{
+    add_headers, execute_get, execute_head, execute_request, find_header, read_body,
+    read_body_with_limit, CommonOptions, PortalError, RequestError,
+};
+use crate::tar::{read_tar_entries, TarEntry, TarError};
+use crate::util::{
+    add_query_params, concat_bytes, concat_strs, de_string_to_bytes, format_number, format_skylink,
+    make_url, ser_bytes_to_string, str_to_bytes, trim_prefix, URI_SKYNET_PREFIX,
+};
 
-use sp_std::{prelude::Vec, str};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sp_core::offchain::StorageKind;
+use sp_io::offchain;
+use sp_runtime::offchain::http;
+use sp_std::{cmp, prelude::Vec, str};
+
+/// The response header holding the skyfile metadata.
+const FILE_METADATA_HEADER: &str = "Skynet-File-Metadata";
+
+/// The response header holding the range of a partial response.
+const CONTENT_RANGE_HEADER: &str = "Content-Range";
+
+/// The response header holding the content length.
+const CONTENT_LENGTH_HEADER: &str = "Content-Length";
+
+/// The response header holding the content type.
+const CONTENT_TYPE_HEADER: &str = "Content-Type";
+
+/// The maximum size of JSON downloads when no maximum response size is given.
+pub const DEFAULT_MAX_JSON_SIZE: u64 = 1 << 20;
+
+/// The default size of the chunks stored by `download_to_local_storage`.
+pub const DEFAULT_LOCAL_STORAGE_CHUNK_SIZE: u32 = 1 << 16;
+
+/// The response header holding the URL of the portal that served the request.
+const PORTAL_API_HEADER: &str = "Skynet-Portal-Api";
+
+/// The response header holding the resolved skylink.
+const SKYLINK_HEADER: &str = "Skynet-Skylink";
 
 /// Download error.
 #[derive(Debug)]
 pub enum DownloadError {
+    /// A response header had an invalid value.
+    InvalidHeader(&'static str),
+    /// A range with a length of zero was requested.
+    InvalidRange,
+    /// JSON error.
+    JsonError(serde_json::Error),
+    /// The downloaded file could not be parsed as JSON.
+    JsonParseError {
+        /// The skylink of the file. For an entry link this is the data link it resolved to.
+        skylink: Vec<u8>,
+        /// The parse error.
+        error: serde_json::Error,
+    },
+    /// An expected response header was missing.
+    MissingHeader(&'static str),
     /// Request error.
     RequestError(RequestError),
+    /// Tar error.
+    TarError(TarError),
+    /// The downloaded file did not have a JSON content type. Holds the content type returned.
+    UnexpectedContentType(Vec<u8>),
     /// UTF8 error.
     Utf8Error(str::Utf8Error),
 }
 
+impl From<serde_json::Error> for DownloadError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::JsonError(err)
+    }
+}
+
 impl From<RequestError> for DownloadError {
     fn from(err: RequestError) -> Self {
         Self::RequestError(err)
     }
 }
 
+impl From<TarError> for DownloadError {
+    fn from(err: TarError) -> Self {
+        Self::TarError(err)
+    }
+}
+
 impl From<str::Utf8Error> for DownloadError {
     fn from(err: str::Utf8Error) -> Self {
         Self::Utf8Error(err)
@@ -33,6 +99,11 @@ pub struct DownloadOptions<'a> {
     pub common: CommonOptions<'a>,
     /// The endpoint to contact.
     pub endpoint_download: &'a str,
+    /// The offset of the first byte to download.
+    pub offset: u64,
+    /// Optional number of bytes to download. If not given, everything after the offset is
+    /// downloaded.
+    pub length: Option<u64>,
 }
 
 impl Default for DownloadOptions<'_> {
@@ -40,30 +111,540 @@ impl Default for DownloadOptions<'_> {
         Self {
             common: Default::default(),
             endpoint_download: "/",
+            offset: 0,
+            length: None,
         }
     }
 }
 
+/// Download to local storage options.
+#[derive(Debug)]
+pub struct DownloadToLocalStorageOptions<'a> {
+    /// Download options.
+    pub download_opts: Option<&'a DownloadOptions<'a>>,
+    /// The maximum size of each chunk stored in offchain storage.
+    pub chunk_size: u32,
+}
+
+impl Default for DownloadToLocalStorageOptions<'_> {
+    fn default() -> Self {
+        Self {
+            download_opts: None,
+            chunk_size: DEFAULT_LOCAL_STORAGE_CHUNK_SIZE,
+        }
+    }
+}
+
+/// The manifest of a download stored in offchain storage. The downloaded bytes are stored in
+/// `chunk_count` chunks of `chunk_size` bytes, except for the last one, which can be shorter.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct LocalStorageManifest {
+    /// The skylink the download resolved to, with the `sia://` prefix.
+    #[serde(
+        serialize_with = "ser_bytes_to_string",
+        deserialize_with = "de_string_to_bytes"
+    )]
+    pub skylink: Vec<u8>,
+    /// The number of bytes downloaded.
+    pub length: u64,
+    /// The maximum size of each chunk.
+    pub chunk_size: u32,
+    /// The number of chunks.
+    pub chunk_count: u32,
+    /// The generation of the chunks. Each download under a key stores its chunks under a new
+    /// generation, so that the previous download stays intact until the new one is complete.
+    #[serde(default)]
+    pub generation: u32,
+}
+
+/// A downloaded range of a file.
+#[derive(Debug, PartialEq)]
+pub struct DownloadRange {
+    /// The downloaded bytes.
+    pub data: Vec<u8>,
+    /// The size of the whole file.
+    pub total_size: u64,
+}
+
+/// A downloaded tar archive along with the files in it. The files are given as ranges of the
+/// archive, so that their contents aren't copied.
+#[derive(Debug, PartialEq)]
+pub struct DownloadedTar {
+    /// The archive.
+    pub archive: Vec<u8>,
+    /// The path of each regular file in the archive and the range of its contents.
+    pub entries: Vec<TarEntry>,
+}
+
+impl DownloadedTar {
+    /// Returns the path and contents of each file, in archive order.
+    pub fn files(&self) -> impl Iterator<Item = (&[u8], &[u8])> {
+        self.entries
+            .iter()
+            .map(|(path, range)| (&path[..], &self.archive[range.clone()]))
+    }
+}
+
+/// A download response.
+#[derive(Debug, PartialEq)]
+pub struct DownloadResponse {
+    /// The downloaded bytes.
+    pub data: Vec<u8>,
+    /// The content type of the file, if returned by the portal.
+    pub content_type: Option<Vec<u8>>,
+    /// The number of bytes downloaded.
+    pub content_length: u64,
+    /// The skylink the download resolved to, with the `sia://` prefix. For an entry link this is
+    /// the data link it pointed to at download time, if returned by the portal, and the entry link
+    /// itself otherwise.
+    pub skylink: Vec<u8>,
+    /// The URL of the portal that served the download.
+    pub portal_url: Vec<u8>,
+}
+
+// The custom metadata is embedded in the skyfile metadata under `CUSTOM_METADATA_FIELD_NAME`.
+#[derive(Deserialize)]
+struct FileMetadata<T> {
+    metadata: Option<T>,
+}
+
 /// Downloads the bytes at the given `skylink`.
 pub fn download_bytes(
     skylink: &str,
     opts: Option<&DownloadOptions>,
 ) -> Result<Vec<u8>, DownloadError> {
+    Ok(download_range(skylink, opts)?.data)
+}
+
+/// Downloads the file at the given `skylink`. Returns the bytes along with the response headers
+/// and the skylink they resolved to.
+pub fn download(
+    skylink: &str,
+    opts: Option<&DownloadOptions>,
+) -> Result<DownloadResponse, DownloadError> {
     let default = Default::default();
     let opts = opts.unwrap_or(&default);
 
-    // TODO: Implement full skylink parsing.
-    let skylink = if let Some(stripped) = skylink.strip_prefix(URI_SKYNET_PREFIX) {
-        stripped
+    let mut response = download_response(skylink, opts)?;
+
+    let headers = response.headers();
+    let content_type = find_header(headers, CONTENT_TYPE_HEADER).map(str_to_bytes);
+    // Report the data link an entry link resolved to, if known.
+    let skylink = format_skylink(&str_to_bytes(trim_prefix(
+        find_header(headers, SKYLINK_HEADER).unwrap_or(skylink),
+        URI_SKYNET_PREFIX,
+    )));
+    // Fall back to the portal we contacted if it doesn't say.
+    let portal_url =
+        str_to_bytes(find_header(headers, PORTAL_API_HEADER).unwrap_or(opts.common.portal_url));
+
+    let data = read_range(response, opts)?.data;
+
+    Ok(DownloadResponse {
+        content_length: data.len() as u64,
+        data,
+        content_type,
+        skylink,
+        portal_url,
+    })
+}
+
+/// Downloads the range of the file at the given `skylink` given by the `offset` and `length`
+/// options. Returns the bytes along with the size of the whole file.
+pub fn download_range(
+    skylink: &str,
+    opts: Option<&DownloadOptions>,
+) -> Result<DownloadRange, DownloadError> {
+    let default = Default::default();
+    let opts = opts.unwrap_or(&default);
+
+    let response = download_response(skylink, opts)?;
+
+    read_range(response, opts)
+}
+
+/// Downloads the JSON file at the given `skylink` and parses it as `T`. The file must have a JSON
+/// content type, and is limited to the maximum response size, or `DEFAULT_MAX_JSON_SIZE` if none
+/// is given.
+pub fn download_json<T: DeserializeOwned>(
+    skylink: &str,
+    opts: Option<&DownloadOptions>,
+) -> Result<T, DownloadError> {
+    let default = Default::default();
+    let opts = opts.unwrap_or(&default);
+
+    let mut response = download_response(skylink, opts)?;
+
+    let headers = response.headers();
+    let content_type = find_header(headers, CONTENT_TYPE_HEADER).unwrap_or_default();
+    if !is_json_content_type(content_type) {
+        return Err(DownloadError::UnexpectedContentType(str_to_bytes(
+            content_type,
+        )));
+    }
+    // Report the data link an entry link resolved to, if known.
+    let skylink = format_skylink(&str_to_bytes(trim_prefix(
+        find_header(headers, SKYLINK_HEADER).unwrap_or(skylink),
+        URI_SKYNET_PREFIX,
+    )));
+
+    let max_size = opts
+        .common
+        .max_response_size
+        .unwrap_or(DEFAULT_MAX_JSON_SIZE);
+    let data = read_body_with_limit(&mut response, Some(max_size))?;
+
+    serde_json::from_slice(&data).map_err(|error| DownloadError::JsonParseError { skylink, error })
+}
+
+/// Downloads the directory at the given `skylink` as a tar archive and reads the files in it. The
+/// archive is returned as is, with the files given as ranges of it.
+pub fn download_tar(
+    skylink: &str,
+    opts: Option<&DownloadOptions>,
+) -> Result<DownloadedTar, DownloadError> {
+    let default = Default::default();
+    let opts = opts.unwrap_or(&default);
+
+    let url = make_download_url(skylink, opts);
+    let url = add_query_params(str::from_utf8(&url)?, &[("format", "tar")]);
+
+    let mut response = execute_get(str::from_utf8(&url)?, &opts.common)?;
+    let archive = read_body(&mut response, &opts.common)?;
+
+    let entries = read_tar_entries(&archive)?;
+
+    Ok(DownloadedTar { archive, entries })
+}
+
+/// Downloads the file at the given `skylink` into offchain storage. The response body is stored
+/// in chunks as it is read, without being held in memory as a whole, and a manifest is stored
+/// under `key`. Any previous download under `key` is replaced once the new one is complete, and
+/// kept if the download fails.
+///
+/// The chunks can be read back with `get_local_storage_chunk`.
+pub fn download_to_local_storage(
+    skylink: &str,
+    key: &[u8],
+    opts: Option<&DownloadToLocalStorageOptions>,
+) -> Result<LocalStorageManifest, DownloadError> {
+    let default = Default::default();
+    let opts = opts.unwrap_or(&default);
+    let default_download = Default::default();
+    let download_opts = opts.download_opts.unwrap_or(&default_download);
+
+    let chunk_size = cmp::max(opts.chunk_size, 1) as usize;
+
+    let mut response = download_response(skylink, download_opts)?;
+
+    // Report the data link an entry link resolved to, if known.
+    let skylink = format_skylink(&str_to_bytes(trim_prefix(
+        find_header(response.headers(), SKYLINK_HEADER).unwrap_or(skylink),
+        URI_SKYNET_PREFIX,
+    )));
+
+    // Take the range from the whole file if the portal ignored it.
+    let (skip, take) = if response.code == 206 {
+        let content_range = find_header(response.headers(), CONTENT_RANGE_HEADER)
+            .ok_or(DownloadError::MissingHeader(CONTENT_RANGE_HEADER))?;
+        let (start, _) = parse_content_range(content_range)
+            .ok_or(DownloadError::InvalidHeader(CONTENT_RANGE_HEADER))?;
+        if start != download_opts.offset {
+            return Err(DownloadError::InvalidHeader(CONTENT_RANGE_HEADER));
+        }
+        (0, download_opts.length)
     } else {
-        skylink
+        (download_opts.offset, download_opts.length)
     };
 
-    let url = make_url(&[opts.common.portal_url, opts.endpoint_download, skylink]);
+    // Fail before storing anything if the portal announces a body that is too large.
+    let max_size = download_opts.common.max_response_size;
+    if let (Some(max_size), Some(content_length)) = (
+        max_size,
+        find_header(response.headers(), CONTENT_LENGTH_HEADER)
+            .and_then(|content_length| content_length.trim().parse::<u64>().ok()),
+    ) {
+        let stored_length = content_length.saturating_sub(skip);
+        if take.map_or(stored_length, |take| cmp::min(take, stored_length)) > max_size {
+            return Err(RequestError::ResponseTooLarge(max_size).into());
+        }
+    }
+
+    let previous = get_local_storage_manifest(key);
+    let generation = previous
+        .as_ref()
+        .map_or(0, |previous| previous.generation.wrapping_add(1));
 
-    let response = execute_get(str::from_utf8(&url)?, &opts.common)?;
+    let mut chunk = Vec::with_capacity(chunk_size);
+    let mut chunk_count = 0;
+    let mut length = 0;
+    // The body is kept to check whether it was read completely.
+    let mut body = response.body();
+    let bytes = body
+        .by_ref()
+        .skip(usize::try_from(skip).unwrap_or(usize::MAX))
+        .take(take.map_or(usize::MAX, |take| {
+            usize::try_from(take).unwrap_or(usize::MAX)
+        }));
+    for byte in bytes {
+        if let Some(max_size) = max_size {
+            if length >= max_size {
+                clear_local_storage_chunks(key, generation, chunk_count);
+                return Err(RequestError::ResponseTooLarge(max_size).into());
+            }
+        }
 
-    Ok(response.body().collect::<Vec<u8>>())
+        chunk.push(byte);
+        length += 1;
+
+        if chunk.len() == chunk_size {
+            set_local_storage_chunk(key, generation, chunk_count, &chunk);
+            chunk.clear();
+            chunk_count += 1;
+        }
+    }
+    // A failed read ends the body early, which must not be stored as the whole download.
+    if let Some(err) = body.error() {
+        clear_local_storage_chunks(key, generation, chunk_count);
+        return Err(RequestError::from(*err).into());
+    }
+    if !chunk.is_empty() {
+        set_local_storage_chunk(key, generation, chunk_count, &chunk);
+        chunk_count += 1;
+    }
+
+    let manifest = LocalStorageManifest {
+        skylink,
+        length,
+        chunk_size: chunk_size as u32,
+        chunk_count,
+        generation,
+    };
+    let bytes = match serde_json::to_vec(&manifest) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            clear_local_storage_chunks(key, generation, chunk_count);
+            return Err(err.into());
+        }
+    };
+    // Swap in the new download, then remove the previous one.
+    offchain::local_storage_set(StorageKind::PERSISTENT, key, &bytes);
+    if let Some(previous) = previous {
+        clear_local_storage_chunks(key, previous.generation, previous.chunk_count);
+    }
+
+    Ok(manifest)
+}
+
+/// Gets the manifest of the download stored under `key` by `download_to_local_storage`.
+pub fn get_local_storage_manifest(key: &[u8]) -> Option<LocalStorageManifest> {
+    let bytes = offchain::local_storage_get(StorageKind::PERSISTENT, key)?;
+
+    serde_json::from_slice(&bytes).ok()
+}
+
+/// Gets the chunk at `index` of the download stored under `key` by `download_to_local_storage`.
+pub fn get_local_storage_chunk(key: &[u8], index: u32) -> Option<Vec<u8>> {
+    let manifest = get_local_storage_manifest(key)?;
+    if index >= manifest.chunk_count {
+        return None;
+    }
+
+    offchain::local_storage_get(
+        StorageKind::PERSISTENT,
+        &local_storage_chunk_key(key, manifest.generation, index),
+    )
+}
+
+/// Removes the download stored under `key` by `download_to_local_storage`.
+pub fn clear_local_storage_download(key: &[u8]) {
+    if let Some(manifest) = get_local_storage_manifest(key) {
+        clear_local_storage_chunks(key, manifest.generation, manifest.chunk_count);
+    }
+    offchain::local_storage_clear(StorageKind::PERSISTENT, key);
+}
+
+/// Checks whether the file at the given `skylink` is available, without downloading it.
+pub fn skylink_exists(
+    skylink: &str,
+    opts: Option<&DownloadOptions>,
+) -> Result<bool, DownloadError> {
+    let default = Default::default();
+    let opts = opts.unwrap_or(&default);
+
+    let url = make_download_url(skylink, opts);
+
+    match execute_head(str::from_utf8(&url)?, &opts.common) {
+        Ok(_) => Ok(true),
+        Err(RequestError::UnexpectedStatus(PortalError { status: 404, .. })) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Gets the size of the file at the given `skylink`, without downloading it.
+pub fn get_file_size(skylink: &str, opts: Option<&DownloadOptions>) -> Result<u64, DownloadError> {
+    let default = Default::default();
+    let opts = opts.unwrap_or(&default);
+
+    let url = make_download_url(skylink, opts);
+
+    let mut response = execute_head(str::from_utf8(&url)?, &opts.common)?;
+
+    find_header(response.headers(), CONTENT_LENGTH_HEADER)
+        .ok_or(DownloadError::MissingHeader(CONTENT_LENGTH_HEADER))?
+        .trim()
+        .parse::<u64>()
+        .map_err(|_| DownloadError::InvalidHeader(CONTENT_LENGTH_HEADER))
+}
+
+/// Downloads the bytes at the given `skylink` along with the custom metadata they were uploaded
+/// with, if any. See `UploadOptions::metadata`.
+pub fn download_bytes_with_metadata<T: DeserializeOwned>(
+    skylink: &str,
+    opts: Option<&DownloadOptions>,
+) -> Result<(Vec<u8>, Option<T>), DownloadError> {
+    let default = Default::default();
+    let opts = opts.unwrap_or(&default);
+
+    let mut response = download_response(skylink, opts)?;
+
+    let metadata = match find_header(response.headers(), FILE_METADATA_HEADER) {
+        Some(file_metadata) => serde_json::from_str::<FileMetadata<T>>(file_metadata)?.metadata,
+        None => None,
+    };
+
+    Ok((read_range(response, opts)?.data, metadata))
+}
+
+fn download_response(
+    skylink: &str,
+    opts: &DownloadOptions,
+) -> Result<http::Response, DownloadError> {
+    let url = make_download_url(skylink, opts);
+
+    let mut request = http::Request::get(str::from_utf8(&url)?);
+    request = add_headers(request, &opts.common);
+
+    // Only request a range if part of the file was asked for.
+    let range = match (opts.offset, opts.length) {
+        (_, Some(0)) => return Err(DownloadError::InvalidRange),
+        (0, None) => None,
+        (offset, None) => Some(concat_strs(&[
+            "bytes=",
+            str::from_utf8(&format_number(offset))?,
+            "-",
+        ])),
+        (offset, Some(length)) => Some(concat_strs(&[
+            "bytes=",
+            str::from_utf8(&format_number(offset))?,
+            "-",
+            str::from_utf8(&format_number(offset.saturating_add(length - 1)))?,
+        ])),
+    };
+    if let Some(range) = &range {
+        request = request.add_header("Range", str::from_utf8(range)?);
+    }
+
+    Ok(execute_request(&request)?)
+}
+
+/// Checks whether `content_type` is `application/json` or a `+json` type, ignoring parameters.
+fn is_json_content_type(content_type: &str) -> bool {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .as_bytes();
+
+    mime.eq_ignore_ascii_case(b"application/json")
+        || (mime.len() > 5 && mime[mime.len() - 5..].eq_ignore_ascii_case(b"+json"))
+}
+
+fn local_storage_chunk_key(key: &[u8], generation: u32, index: u32) -> Vec<u8> {
+    concat_bytes(&[
+        key,
+        b"::",
+        &format_number(generation as u64),
+        b"::",
+        &format_number(index as u64),
+    ])
+}
+
+fn set_local_storage_chunk(key: &[u8], generation: u32, index: u32, chunk: &[u8]) {
+    offchain::local_storage_set(
+        StorageKind::PERSISTENT,
+        &local_storage_chunk_key(key, generation, index),
+        chunk,
+    );
+}
+
+fn clear_local_storage_chunks(key: &[u8], generation: u32, chunk_count: u32) {
+    for index in 0..chunk_count {
+        offchain::local_storage_clear(
+            StorageKind::PERSISTENT,
+            &local_storage_chunk_key(key, generation, index),
+        );
+    }
+}
+
+fn make_download_url(skylink: &str, opts: &DownloadOptions) -> Vec<u8> {
+    // TODO: Implement full skylink parsing.
+    let skylink = trim_prefix(skylink, URI_SKYNET_PREFIX);
+
+    make_url(&[opts.common.portal_url, opts.endpoint_download, skylink])
+}
+
+/// Reads the requested range from the `response`. Portals may ignore the range and send the whole
+/// file, in which case the range is taken from it here.
+fn read_range(
+    mut response: http::Response,
+    opts: &DownloadOptions,
+) -> Result<DownloadRange, DownloadError> {
+    if response.code == 206 {
+        let content_range = find_header(response.headers(), CONTENT_RANGE_HEADER)
+            .ok_or(DownloadError::MissingHeader(CONTENT_RANGE_HEADER))?;
+        let (start, total_size) = parse_content_range(content_range)
+            .ok_or(DownloadError::InvalidHeader(CONTENT_RANGE_HEADER))?;
+        if start != opts.offset {
+            return Err(DownloadError::InvalidHeader(CONTENT_RANGE_HEADER));
+        }
+
+        let mut data = read_body(&mut response, &opts.common)?;
+        if let Some(length) = opts.length {
+            data.truncate(cmp::min(length, data.len() as u64) as usize);
+        }
+
+        return Ok(DownloadRange { data, total_size });
+    }
+
+    let mut data = read_body(&mut response, &opts.common)?;
+    let total_size = data.len() as u64;
+
+    let start = cmp::min(opts.offset, total_size);
+    let end = opts.length.map_or(total_size, |length| {
+        cmp::min(start.saturating_add(length), total_size)
+    });
+    data.truncate(end as usize);
+    data.drain(..start as usize);
+
+    Ok(DownloadRange { data, total_size })
+}
+
+/// Parses a `Content-Range` header of the form `bytes <start>-<end>/<size>`. Returns the start and
+/// the size of the whole file.
+fn parse_content_range(content_range: &str) -> Option<(u64, u64)> {
+    let (range, size) = content_range.strip_prefix("bytes ")?.split_once('/')?;
+    let (start, end) = range.split_once('-')?;
+
+    let start = start.trim().parse::<u64>().ok()?;
+    let end = end.trim().parse::<u64>().ok()?;
+    let size = size.trim().parse::<u64>().ok()?;
+    if start > end || end >= size {
+        return None;
+    }
+
+    Some((start, size))
 }
 
 #[cfg(test)]
@@ -71,7 +652,8 @@ mod tests {
     use super::*;
     use crate::util::str_to_bytes;
 
-    use sp_core::offchain::{testing, OffchainWorkerExt};
+    use serde::Deserialize;
+    use sp_core::offchain::{testing, HttpError, OffchainDbExt, OffchainWorkerExt};
     use sp_io::TestExternalities;
 
     const DATA_LINK: &str = "MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ";
@@ -138,4 +720,632 @@ mod tests {
             assert_eq!(data_returned, str_to_bytes(EXPECTED_JSON));
         })
     }
+
+    #[test]
+    fn should_download_with_custom_metadata() {
+        const FILE_METADATA: &str = "{\"filename\":\"barfile\",\"length\":3,\"metadata\":{\"block\":42,\"extrinsic\":\"0x01\"}}";
+
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct Provenance {
+            block: u32,
+            extrinsic: String,
+        }
+
+        let (offchain, state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainWorkerExt::new(offchain));
+
+        // Add expected requests.
+        state.write().expect_request(testing::PendingRequest {
+            method: "GET".into(),
+            uri: "https://siasky.net/MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ".into(),
+            response: Some(str_to_bytes(EXPECTED_JSON)),
+            response_headers: vec![
+                ("Skynet-Skylink".to_owned(), DATA_LINK.to_owned()),
+                ("Skynet-File-Metadata".to_owned(), FILE_METADATA.to_owned()),
+            ],
+            sent: true,
+            ..Default::default()
+        });
+        state.write().expect_request(testing::PendingRequest {
+            method: "GET".into(),
+            uri: "https://siasky.net/MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ".into(),
+            response: Some(str_to_bytes(EXPECTED_JSON)),
+            response_headers: vec![("Skynet-Skylink".to_owned(), DATA_LINK.to_owned())],
+            sent: true,
+            ..Default::default()
+        });
+
+        t.execute_with(|| {
+            // Download with metadata.
+            let (data_returned, metadata_returned) =
+                download_bytes_with_metadata::<Provenance>(DATA_LINK, None).unwrap();
+            assert_eq!(data_returned, str_to_bytes(EXPECTED_JSON));
+            assert_eq!(
+                metadata_returned,
+                Some(Provenance {
+                    block: 42,
+                    extrinsic: "0x01".to_owned(),
+                })
+            );
+
+            // Download without metadata.
+            let (data_returned, metadata_returned) =
+                download_bytes_with_metadata::<Provenance>(DATA_LINK, None).unwrap();
+            assert_eq!(data_returned, str_to_bytes(EXPECTED_JSON));
+            assert_eq!(metadata_returned, None);
+        })
+    }
+
+    #[test]
+    fn should_parse_content_range() {
+        assert_eq!(parse_content_range("bytes 0-99/1234"), Some((0, 1234)));
+        assert_eq!(
+            parse_content_range("bytes 100-1233/1234"),
+            Some((100, 1234))
+        );
+
+        assert_eq!(parse_content_range("bytes 0-99/*"), None);
+        assert_eq!(parse_content_range("bytes */1234"), None);
+        assert_eq!(parse_content_range("bytes 100-99/1234"), None);
+        assert_eq!(parse_content_range("bytes 0-1234/1234"), None);
+        assert_eq!(parse_content_range("0-99/1234"), None);
+    }
+
+    #[test]
+    fn should_download_range() {
+        let (offchain, state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainWorkerExt::new(offchain));
+
+        // Add expected requests. The test portal ignores the range and returns the whole file.
+        state.write().expect_request(testing::PendingRequest {
+            method: "GET".into(),
+            uri: "https://siasky.net/MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ".into(),
+            headers: vec![("Range".to_owned(), "bytes=2-8".to_owned())],
+            response: Some(str_to_bytes(EXPECTED_JSON)),
+            sent: true,
+            ..Default::default()
+        });
+        state.write().expect_request(testing::PendingRequest {
+            method: "GET".into(),
+            uri: "https://siasky.net/MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ".into(),
+            headers: vec![("Range".to_owned(), "bytes=20-".to_owned())],
+            response: Some(str_to_bytes(EXPECTED_JSON)),
+            sent: true,
+            ..Default::default()
+        });
+
+        t.execute_with(|| {
+            // Download a range with a length.
+            let range_returned = download_range(
+                DATA_LINK,
+                Some(&DownloadOptions {
+                    offset: 2,
+                    length: Some(7),
+                    ..Default::default()
+                }),
+            )
+            .unwrap();
+            assert_eq!(
+                range_returned,
+                DownloadRange {
+                    data: str_to_bytes("message"),
+                    total_size: EXPECTED_JSON.len() as u64,
+                }
+            );
+
+            // Download everything after an offset.
+            let data_returned = download_bytes(
+                DATA_LINK,
+                Some(&DownloadOptions {
+                    offset: 20,
+                    ..Default::default()
+                }),
+            )
+            .unwrap();
+            assert_eq!(data_returned, str_to_bytes("!\" }"));
+
+            // Download an empty range.
+            let result = download_range(
+                DATA_LINK,
+                Some(&DownloadOptions {
+                    length: Some(0),
+                    ..Default::default()
+                }),
+            );
+            assert!(matches!(result, Err(DownloadError::InvalidRange)));
+        })
+    }
+
+    #[test]
+    fn should_download_with_response_headers() {
+        const ENTRY_LINK: &str = "AQDwh1jnoZas9LaLHC_D4-2yP9XYDdZzNtz62H4Dww1jDA";
+
+        let (offchain, state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainWorkerExt::new(offchain));
+
+        // Add expected request.
+        state.write().expect_request(testing::PendingRequest {
+            method: "GET".into(),
+            uri: "https://siasky.net/AQDwh1jnoZas9LaLHC_D4-2yP9XYDdZzNtz62H4Dww1jDA".into(),
+            response: Some(str_to_bytes(EXPECTED_JSON)),
+            response_headers: vec![
+                ("content-type".to_owned(), "application/json".to_owned()),
+                ("skynet-skylink".to_owned(), DATA_LINK.to_owned()),
+                (
+                    "skynet-portal-api".to_owned(),
+                    "https://siasky.xyz".to_owned(),
+                ),
+            ],
+            sent: true,
+            ..Default::default()
+        });
+
+        t.execute_with(|| {
+            // Download
+            let response_returned = download(ENTRY_LINK, None).unwrap();
+
+            // Check the response.
+            assert_eq!(
+                response_returned,
+                DownloadResponse {
+                    data: str_to_bytes(EXPECTED_JSON),
+                    content_type: Some(str_to_bytes("application/json")),
+                    content_length: EXPECTED_JSON.len() as u64,
+                    skylink: format_skylink(&str_to_bytes(DATA_LINK)),
+                    portal_url: str_to_bytes("https://siasky.xyz"),
+                }
+            );
+        })
+    }
+
+    #[test]
+    fn should_download_without_skylink_header() {
+        let (offchain, state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainWorkerExt::new(offchain));
+
+        // Add expected request.
+        state.write().expect_request(testing::PendingRequest {
+            method: "GET".into(),
+            uri: "https://siasky.net/MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ".into(),
+            response: Some(str_to_bytes(EXPECTED_JSON)),
+            sent: true,
+            ..Default::default()
+        });
+
+        t.execute_with(|| {
+            // Download
+            let response_returned =
+                download(&format!("{}{}", URI_SKYNET_PREFIX, DATA_LINK), None).unwrap();
+
+            // The requested skylink is reported.
+            assert_eq!(
+                response_returned.skylink,
+                format_skylink(&str_to_bytes(DATA_LINK))
+            );
+            assert_eq!(response_returned.content_type, None);
+        })
+    }
+
+    #[test]
+    fn should_check_existence_and_size_without_downloading() {
+        let (offchain, state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainWorkerExt::new(offchain));
+
+        // Add expected requests.
+        for _ in 0..2 {
+            state.write().expect_request(testing::PendingRequest {
+                method: "HEAD".into(),
+                uri: "https://siasky.net/MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ".into(),
+                response: Some(Vec::new()),
+                response_headers: vec![
+                    ("Content-Length".to_owned(), "1234".to_owned()),
+                    ("Skynet-Skylink".to_owned(), DATA_LINK.to_owned()),
+                ],
+                sent: true,
+                ..Default::default()
+            });
+        }
+
+        t.execute_with(|| {
+            assert!(skylink_exists(DATA_LINK, None).unwrap());
+            assert_eq!(get_file_size(DATA_LINK, None).unwrap(), 1234);
+        })
+    }
+
+    #[test]
+    fn should_fail_to_download_too_large_response() {
+        let (offchain, state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainWorkerExt::new(offchain));
+
+        // Add expected requests, with and without a content length.
+        state.write().expect_request(testing::PendingRequest {
+            method: "GET".into(),
+            uri: "https://siasky.net/MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ".into(),
+            response: Some(str_to_bytes(EXPECTED_JSON)),
+            response_headers: vec![("Content-Length".to_owned(), EXPECTED_JSON.len().to_string())],
+            sent: true,
+            ..Default::default()
+        });
+        for _ in 0..2 {
+            state.write().expect_request(testing::PendingRequest {
+                method: "GET".into(),
+                uri: "https://siasky.net/MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ".into(),
+                response: Some(str_to_bytes(EXPECTED_JSON)),
+                sent: true,
+                ..Default::default()
+            });
+        }
+
+        t.execute_with(|| {
+            let opts = DownloadOptions {
+                common: CommonOptions {
+                    max_response_size: Some(10),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+
+            // Rejected up front.
+            let result = download_bytes(DATA_LINK, Some(&opts));
+            assert!(matches!(
+                result,
+                Err(DownloadError::RequestError(RequestError::ResponseTooLarge(
+                    10
+                )))
+            ));
+
+            // Rejected while reading.
+            let result = download_bytes(DATA_LINK, Some(&opts));
+            assert!(matches!(
+                result,
+                Err(DownloadError::RequestError(RequestError::ResponseTooLarge(
+                    10
+                )))
+            ));
+
+            // A response of exactly the maximum size is allowed.
+            let opts = DownloadOptions {
+                common: CommonOptions {
+                    max_response_size: Some(EXPECTED_JSON.len() as u64),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            let data_returned = download_bytes(DATA_LINK, Some(&opts)).unwrap();
+            assert_eq!(data_returned, str_to_bytes(EXPECTED_JSON));
+        })
+    }
+
+    #[test]
+    fn should_download_tar() {
+        use crate::tar::tests::{make_tar, make_tar_entry};
+
+        let archive = make_tar(&[
+            make_tar_entry("index.html", "", b'0', b"<html></html>"),
+            make_tar_entry("app.js", "assets", b'0', b"foo"),
+        ]);
+
+        let (offchain, state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainWorkerExt::new(offchain));
+
+        // Add expected request.
+        state.write().expect_request(testing::PendingRequest {
+            method: "GET".into(),
+            uri: "https://siasky.net/MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ?format=tar"
+                .into(),
+            response: Some(archive),
+            sent: true,
+            ..Default::default()
+        });
+
+        t.execute_with(|| {
+            // Download
+            let tar_returned = download_tar(DATA_LINK, None).unwrap();
+
+            // Check the response.
+            assert_eq!(
+                tar_returned.files().collect::<Vec<_>>(),
+                vec![
+                    (&b"index.html"[..], &b"<html></html>"[..]),
+                    (&b"assets/app.js"[..], &b"foo"[..]),
+                ]
+            );
+        })
+    }
+
+    #[test]
+    fn should_check_json_content_types() {
+        assert!(is_json_content_type("application/json"));
+        assert!(is_json_content_type("Application/JSON; charset=utf-8"));
+        assert!(is_json_content_type("application/ld+json"));
+
+        assert!(!is_json_content_type(""));
+        assert!(!is_json_content_type("+json"));
+        assert!(!is_json_content_type("text/plain"));
+        assert!(!is_json_content_type("application/jsonp"));
+    }
+
+    #[test]
+    fn should_download_json() {
+        const ENTRY_LINK: &str = "AQDwh1jnoZas9LaLHC_D4-2yP9XYDdZzNtz62H4Dww1jDA";
+
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct Config {
+            version: u32,
+        }
+
+        let (offchain, state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainWorkerExt::new(offchain));
+
+        // Add expected requests.
+        state.write().expect_request(testing::PendingRequest {
+            method: "GET".into(),
+            uri: "https://siasky.net/MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ".into(),
+            response: Some(str_to_bytes("{\"version\": 2}")),
+            response_headers: vec![
+                ("Content-Type".to_owned(), "application/json".to_owned()),
+                ("Skynet-Skylink".to_owned(), DATA_LINK.to_owned()),
+            ],
+            sent: true,
+            ..Default::default()
+        });
+        state.write().expect_request(testing::PendingRequest {
+            method: "GET".into(),
+            uri: "https://siasky.net/AQDwh1jnoZas9LaLHC_D4-2yP9XYDdZzNtz62H4Dww1jDA".into(),
+            response: Some(str_to_bytes(EXPECTED_JSON)),
+            response_headers: vec![
+                ("Content-Type".to_owned(), "application/json".to_owned()),
+                ("Skynet-Skylink".to_owned(), DATA_LINK.to_owned()),
+            ],
+            sent: true,
+            ..Default::default()
+        });
+        state.write().expect_request(testing::PendingRequest {
+            method: "GET".into(),
+            uri: "https://siasky.net/MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ".into(),
+            response: Some(str_to_bytes("{\"version\": 2}")),
+            response_headers: vec![("Content-Type".to_owned(), "text/plain".to_owned())],
+            sent: true,
+            ..Default::default()
+        });
+
+        t.execute_with(|| {
+            // Download a data link.
+            let config_returned = download_json::<Config>(DATA_LINK, None).unwrap();
+            assert_eq!(config_returned, Config { version: 2 });
+
+            // Download an entry link pointing to invalid JSON.
+            match download_json::<Config>(ENTRY_LINK, None) {
+                Err(DownloadError::JsonParseError { skylink, .. }) => {
+                    assert_eq!(skylink, format_skylink(&str_to_bytes(DATA_LINK)))
+                }
+                result => panic!("unexpected result: {:?}", result),
+            }
+
+            // Download a file that is not JSON.
+            match download_json::<Config>(DATA_LINK, None) {
+                Err(DownloadError::UnexpectedContentType(content_type)) => {
+                    assert_eq!(content_type, str_to_bytes("text/plain"))
+                }
+                result => panic!("unexpected result: {:?}", result),
+            }
+        })
+    }
+
+    #[test]
+    fn should_download_to_local_storage() {
+        const KEY: &[u8] = b"skynet::download::data";
+
+        let (offchain, state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainWorkerExt::new(offchain.clone()));
+        t.register_extension(OffchainDbExt::new(offchain));
+
+        // Add expected requests.
+        for _ in 0..2 {
+            state.write().expect_request(testing::PendingRequest {
+                method: "GET".into(),
+                uri: "https://siasky.net/MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ".into(),
+                response: Some(str_to_bytes(EXPECTED_JSON)),
+                response_headers: vec![("Skynet-Skylink".to_owned(), DATA_LINK.to_owned())],
+                sent: true,
+                ..Default::default()
+            });
+        }
+
+        t.execute_with(|| {
+            // Download in chunks of 10 bytes.
+            let manifest_returned = download_to_local_storage(
+                DATA_LINK,
+                KEY,
+                Some(&DownloadToLocalStorageOptions {
+                    chunk_size: 10,
+                    ..Default::default()
+                }),
+            )
+            .unwrap();
+
+            let expected_manifest = LocalStorageManifest {
+                skylink: format_skylink(&str_to_bytes(DATA_LINK)),
+                length: EXPECTED_JSON.len() as u64,
+                chunk_size: 10,
+                chunk_count: 3,
+                generation: 0,
+            };
+            assert_eq!(manifest_returned, expected_manifest);
+            assert_eq!(get_local_storage_manifest(KEY), Some(expected_manifest));
+
+            // Read the chunks back.
+            let data = (0..manifest_returned.chunk_count)
+                .flat_map(|index| get_local_storage_chunk(KEY, index).unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(data, str_to_bytes(EXPECTED_JSON));
+            assert_eq!(get_local_storage_chunk(KEY, 3), None);
+
+            // Replace the download with a single chunk.
+            let manifest_returned = download_to_local_storage(DATA_LINK, KEY, None).unwrap();
+            assert_eq!(manifest_returned.chunk_count, 1);
+            assert_eq!(manifest_returned.generation, 1);
+            assert_eq!(
+                get_local_storage_chunk(KEY, 0),
+                Some(str_to_bytes(EXPECTED_JSON))
+            );
+            assert_eq!(get_local_storage_chunk(KEY, 1), None);
+            // The previous chunks are removed.
+            assert_eq!(
+                offchain::local_storage_get(
+                    StorageKind::PERSISTENT,
+                    &local_storage_chunk_key(KEY, 0, 1)
+                ),
+                None
+            );
+
+            // Clear the download.
+            clear_local_storage_download(KEY);
+            assert_eq!(get_local_storage_manifest(KEY), None);
+            assert_eq!(get_local_storage_chunk(KEY, 0), None);
+        })
+    }
+
+    #[test]
+    fn should_keep_previous_local_storage_download_on_failure() {
+        use crate::request::tests::StatusOffchainExt;
+
+        const KEY: &[u8] = b"skynet::download::data";
+
+        let (offchain, state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainWorkerExt::new(
+            StatusOffchainExt::new(offchain.clone(), &[(3, 206)]).with_failing_body(4),
+        ));
+        t.register_extension(OffchainDbExt::new(offchain));
+
+        // Add expected requests.
+        state.write().expect_request(testing::PendingRequest {
+            method: "GET".into(),
+            uri: "https://siasky.net/MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ".into(),
+            response: Some(str_to_bytes(EXPECTED_JSON)),
+            sent: true,
+            ..Default::default()
+        });
+        // Too large according to its content length.
+        state.write().expect_request(testing::PendingRequest {
+            method: "GET".into(),
+            uri: "https://siasky.net/MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ".into(),
+            response: Some(str_to_bytes("foo")),
+            response_headers: vec![("Content-Length".to_owned(), "100".to_owned())],
+            sent: true,
+            ..Default::default()
+        });
+        // Too large while reading.
+        state.write().expect_request(testing::PendingRequest {
+            method: "GET".into(),
+            uri: "https://siasky.net/MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ".into(),
+            response: Some(vec![0; 100]),
+            sent: true,
+            ..Default::default()
+        });
+        // A partial response for the wrong range.
+        state.write().expect_request(testing::PendingRequest {
+            method: "GET".into(),
+            uri: "https://siasky.net/MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ".into(),
+            headers: vec![("Range".to_owned(), "bytes=2-".to_owned())],
+            response: Some(str_to_bytes("foo")),
+            response_headers: vec![("Content-Range".to_owned(), "bytes 0-2/3".to_owned())],
+            sent: true,
+            ..Default::default()
+        });
+        // Cut off after the first chunk.
+        state.write().expect_request(testing::PendingRequest {
+            method: "GET".into(),
+            uri: "https://siasky.net/MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ".into(),
+            response: Some(vec![0; 100]),
+            sent: true,
+            ..Default::default()
+        });
+
+        t.execute_with(|| {
+            let expected_manifest = download_to_local_storage(
+                DATA_LINK,
+                KEY,
+                Some(&DownloadToLocalStorageOptions {
+                    chunk_size: 10,
+                    ..Default::default()
+                }),
+            )
+            .unwrap();
+
+            let download_opts = DownloadOptions {
+                common: CommonOptions {
+                    max_response_size: Some(50),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            let opts = DownloadToLocalStorageOptions {
+                download_opts: Some(&download_opts),
+                chunk_size: 10,
+            };
+            for _ in 0..2 {
+                assert!(matches!(
+                    download_to_local_storage(DATA_LINK, KEY, Some(&opts)),
+                    Err(DownloadError::RequestError(RequestError::ResponseTooLarge(
+                        50
+                    )))
+                ));
+            }
+
+            let download_opts = DownloadOptions {
+                offset: 2,
+                ..Default::default()
+            };
+            assert!(matches!(
+                download_to_local_storage(
+                    DATA_LINK,
+                    KEY,
+                    Some(&DownloadToLocalStorageOptions {
+                        download_opts: Some(&download_opts),
+                        ..Default::default()
+                    })
+                ),
+                Err(DownloadError::InvalidHeader(CONTENT_RANGE_HEADER))
+            ));
+
+            assert!(matches!(
+                download_to_local_storage(
+                    DATA_LINK,
+                    KEY,
+                    Some(&DownloadToLocalStorageOptions {
+                        chunk_size: 10,
+                        ..Default::default()
+                    })
+                ),
+                Err(DownloadError::RequestError(RequestError::HttpError(
+                    HttpError::DeadlineReached
+                )))
+            ));
+
+            // The previous download is intact, and nothing was left of the failed ones.
+            assert_eq!(get_local_storage_manifest(KEY), Some(expected_manifest));
+            let data = (0..3)
+                .flat_map(|index| get_local_storage_chunk(KEY, index).unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(data, str_to_bytes(EXPECTED_JSON));
+            assert_eq!(
+                offchain::local_storage_get(
+                    StorageKind::PERSISTENT,
+                    &local_storage_chunk_key(KEY, 1, 0)
+                ),
+                None
+            );
+        })
+    }
 }
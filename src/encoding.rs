@@ -26,6 +26,16 @@ pub fn encode_skylink_base64(bytes: &[u8]) -> Vec<u8> {
     buf
 }
 
+/// Encodes the bytes using standard base64 encoding with padding.
+pub fn encode_base64(bytes: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    // Make sure we'll have a slice big enough.
+    buf.resize(bytes.len().div_ceil(3) * 4, 0);
+
+    let _ = base64::encode_config_slice(bytes, base64::STANDARD, &mut buf);
+    buf
+}
+
 pub fn decode_hex_to_bytes(hex: &str) -> Vec<u8> {
     decode_hex_bytes_to_bytes(&str_to_bytes(hex))
 }
@@ -154,6 +164,14 @@ mod tests {
         assert_eq!(skylink, str::from_utf8(&encoded_bytes).unwrap());
     }
 
+    #[test]
+    fn should_encode_base64() {
+        assert_eq!(encode_base64(b""), str_to_bytes(""));
+        assert_eq!(encode_base64(b"f"), str_to_bytes("Zg=="));
+        assert_eq!(encode_base64(b"foobar"), str_to_bytes("Zm9vYmFy"));
+        assert_eq!(encode_base64(b"file.json"), str_to_bytes("ZmlsZS5qc29u"));
+    }
+
     #[test]
     fn should_decode_hex() {
         let s = decode_hex_to_bytes("ff");
@@ -0,0 +1,279 @@
+//! Client-side encryption functions.
+
+use crate::download::{download_bytes, DownloadError, DownloadOptions};
+use crate::upload::{upload_chunks, UploadError, UploadOptions, UploadResult};
+
+use chacha20poly1305::aead::{Aead, NewAead, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use sp_io::offchain;
+use sp_std::prelude::Vec;
+
+/// The encryption key type.
+pub type EncryptionKey = [u8; ENCRYPTION_KEY_LENGTH];
+
+/// The encryption key length.
+pub const ENCRYPTION_KEY_LENGTH: usize = 32;
+
+/// The version of the encrypted file format. Stored as the first byte of the header.
+const ENCRYPTION_VERSION: u8 = 1;
+
+/// The nonce length for XChaCha20-Poly1305.
+const NONCE_LENGTH: usize = 24;
+
+/// The length of the header, consisting of the version and the nonce.
+const HEADER_LENGTH: usize = 1 + NONCE_LENGTH;
+
+/// The content type sent for encrypted files, whatever the type of the plaintext.
+const ENCRYPTED_CONTENT_TYPE: &str = "application/octet-stream";
+
+/// Encryption error.
+#[derive(Debug)]
+pub enum EncryptionError {
+    /// The data could not be decrypted with the given key, or it was tampered with.
+    DecryptionFailed,
+    /// Download error.
+    DownloadError(DownloadError),
+    /// The data could not be encrypted.
+    EncryptionFailed,
+    /// The data is too short to contain the encryption header.
+    InvalidHeader,
+    /// Custom metadata was given, which would be uploaded unencrypted.
+    UnencryptedMetadata,
+    /// The data was encrypted with an unsupported version.
+    UnsupportedVersion(u8),
+    /// Upload error.
+    UploadError(UploadError),
+}
+
+impl From<DownloadError> for EncryptionError {
+    fn from(err: DownloadError) -> Self {
+        Self::DownloadError(err)
+    }
+}
+
+impl From<UploadError> for EncryptionError {
+    fn from(err: UploadError) -> Self {
+        Self::UploadError(err)
+    }
+}
+
+/// Encrypts `bytes` with a new random key and uploads them to a file with `filename`. Returns the
+/// upload result along with the key needed to decrypt the file.
+///
+/// Only the contents are encrypted; `filename` is uploaded as it is. The content type is always
+/// sent as `application/octet-stream` so that it doesn't reveal the type of the plaintext, and
+/// custom metadata is rejected as it can't be encrypted.
+pub fn upload_encrypted(
+    bytes: &[u8],
+    filename: &str,
+    opts: Option<&UploadOptions>,
+) -> Result<(UploadResult, EncryptionKey), EncryptionError> {
+    let default = Default::default();
+    let opts = opts.unwrap_or(&default);
+
+    if opts.metadata.is_some() {
+        return Err(EncryptionError::UnencryptedMetadata);
+    }
+    let opts = UploadOptions {
+        common: opts.common.clone(),
+        content_type: Some(ENCRYPTED_CONTENT_TYPE),
+        ..*opts
+    };
+
+    // Use a new key for every file.
+    let key = offchain::random_seed();
+    let nonce = offchain::random_seed();
+    let mut nonce_bytes = [0; NONCE_LENGTH];
+    nonce_bytes.copy_from_slice(&nonce[..NONCE_LENGTH]);
+
+    let (header, ciphertext) = encrypt_bytes(bytes, &key, &nonce_bytes)?;
+
+    let upload_result = upload_chunks([&header[..], &ciphertext], filename, Some(&opts))?;
+    Ok((upload_result, key))
+}
+
+/// Downloads the file at the given `skylink` and decrypts it with `key`.
+pub fn download_decrypted(
+    skylink: &str,
+    key: &EncryptionKey,
+    opts: Option<&DownloadOptions>,
+) -> Result<Vec<u8>, EncryptionError> {
+    let data = download_bytes(skylink, opts)?;
+
+    decrypt_bytes(&data, key)
+}
+
+/// Encrypts `bytes` with `key` and `nonce`. Returns the header and the ciphertext. The header is
+/// authenticated along with the ciphertext.
+fn encrypt_bytes(
+    bytes: &[u8],
+    key: &EncryptionKey,
+    nonce: &[u8; NONCE_LENGTH],
+) -> Result<([u8; HEADER_LENGTH], Vec<u8>), EncryptionError> {
+    let mut header = [0; HEADER_LENGTH];
+    header[0] = ENCRYPTION_VERSION;
+    header[1..].copy_from_slice(nonce);
+
+    let cipher = XChaCha20Poly1305::new(Key::from_slice(key));
+    let ciphertext = cipher
+        .encrypt(
+            XNonce::from_slice(nonce),
+            Payload {
+                msg: bytes,
+                aad: &header,
+            },
+        )
+        .map_err(|_| EncryptionError::EncryptionFailed)?;
+
+    Ok((header, ciphertext))
+}
+
+/// Decrypts `data`, consisting of the header followed by the ciphertext, with `key`.
+fn decrypt_bytes(data: &[u8], key: &EncryptionKey) -> Result<Vec<u8>, EncryptionError> {
+    if data.len() < HEADER_LENGTH {
+        return Err(EncryptionError::InvalidHeader);
+    }
+    let (header, ciphertext) = data.split_at(HEADER_LENGTH);
+
+    if header[0] != ENCRYPTION_VERSION {
+        return Err(EncryptionError::UnsupportedVersion(header[0]));
+    }
+
+    let cipher = XChaCha20Poly1305::new(Key::from_slice(key));
+    cipher
+        .decrypt(
+            XNonce::from_slice(&header[1..]),
+            Payload {
+                msg: ciphertext,
+                aad: header,
+            },
+        )
+        .map_err(|_| EncryptionError::DecryptionFailed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::{concat_bytes, str_to_bytes};
+
+    use sp_core::offchain::{testing, OffchainWorkerExt};
+    use sp_io::TestExternalities;
+
+    const DATA: &str = "foo";
+    // The content type sent is not detected from the extension.
+    const FILE_NAME: &str = "secret.json";
+    const DATA_LINK: &str = "MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ";
+    const EXPECTED_DATA_LINK: &str = "sia://MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ";
+    const RESPONSE_JSON: &str = "{\"skylink\": \"MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ\", \"merkleroot\": \"5d59692ee98113336ce8a060a3422dba46ce1ae876fbe2adba8bebcc31cc6299\", \"bitfield\": 48}";
+    const REQUEST_BODY_PREAMBLE: &str = "--0000000000000000000000000000000000000000000000000000000000000000----\r\nContent-Disposition: form-data; name=\"file\"; filename=\"secret.json\"\r\nContent-Type: application/octet-stream\r\n\r\n";
+    const REQUEST_BODY_EPILOGUE: &str =
+        "\r\n--0000000000000000000000000000000000000000000000000000000000000000------\r\n";
+    const CONTENT_TYPE_MULTIPART: &str = "multipart/form-data; boundary=\"0000000000000000000000000000000000000000000000000000000000000000----\"";
+
+    #[test]
+    fn should_encrypt_and_decrypt_bytes() {
+        let key = [1; ENCRYPTION_KEY_LENGTH];
+        let nonce = [2; NONCE_LENGTH];
+
+        let (header, ciphertext) = encrypt_bytes(DATA.as_bytes(), &key, &nonce).unwrap();
+        assert_eq!(header[0], ENCRYPTION_VERSION);
+        assert_eq!(header[1..], nonce);
+        assert_ne!(ciphertext, str_to_bytes(DATA));
+
+        let data = concat_bytes(&[&header, &ciphertext]);
+        assert_eq!(decrypt_bytes(&data, &key).unwrap(), str_to_bytes(DATA));
+    }
+
+    #[test]
+    fn should_fail_to_decrypt_with_wrong_key_or_tampered_data() {
+        let key = [1; ENCRYPTION_KEY_LENGTH];
+        let nonce = [2; NONCE_LENGTH];
+
+        let (header, ciphertext) = encrypt_bytes(DATA.as_bytes(), &key, &nonce).unwrap();
+        let mut data = concat_bytes(&[&header, &ciphertext]);
+
+        // Wrong key.
+        assert!(matches!(
+            decrypt_bytes(&data, &[3; ENCRYPTION_KEY_LENGTH]),
+            Err(EncryptionError::DecryptionFailed)
+        ));
+
+        // Tampered nonce.
+        data[1] ^= 1;
+        assert!(matches!(
+            decrypt_bytes(&data, &key),
+            Err(EncryptionError::DecryptionFailed)
+        ));
+
+        // Unsupported version.
+        data[0] = 2;
+        assert!(matches!(
+            decrypt_bytes(&data, &key),
+            Err(EncryptionError::UnsupportedVersion(2))
+        ));
+
+        // Too short.
+        assert!(matches!(
+            decrypt_bytes(&data[..HEADER_LENGTH - 1], &key),
+            Err(EncryptionError::InvalidHeader)
+        ));
+    }
+
+    #[test]
+    fn should_upload_encrypted_and_download_decrypted() {
+        let (offchain, state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainWorkerExt::new(offchain));
+
+        // The test random seed is all zeroes.
+        let key = [0; ENCRYPTION_KEY_LENGTH];
+        let (header, ciphertext) =
+            encrypt_bytes(DATA.as_bytes(), &key, &[0; NONCE_LENGTH]).unwrap();
+        let encrypted = concat_bytes(&[&header, &ciphertext]);
+
+        // Add expected requests.
+        state.write().expect_request(testing::PendingRequest {
+            method: "POST".into(),
+            uri: "https://siasky.net/skynet/skyfile".into(),
+            body: concat_bytes(&[
+                REQUEST_BODY_PREAMBLE.as_bytes(),
+                &encrypted,
+                REQUEST_BODY_EPILOGUE.as_bytes(),
+            ]),
+            headers: vec![("Content-Type".to_owned(), CONTENT_TYPE_MULTIPART.to_owned())],
+            response: Some(RESPONSE_JSON.into()),
+            sent: true,
+            ..Default::default()
+        });
+        state.write().expect_request(testing::PendingRequest {
+            method: "GET".into(),
+            uri: "https://siasky.net/MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ".into(),
+            response: Some(encrypted),
+            sent: true,
+            ..Default::default()
+        });
+
+        t.execute_with(|| {
+            // Upload
+            let (result_returned, key_returned) =
+                upload_encrypted(DATA.as_bytes(), FILE_NAME, None).unwrap();
+            assert_eq!(result_returned.skylink, str_to_bytes(EXPECTED_DATA_LINK));
+            assert_eq!(key_returned, key);
+
+            // Custom metadata is rejected before anything is uploaded.
+            let metadata = serde_json::json!({ "secret": true });
+            let opts = UploadOptions {
+                metadata: Some(&metadata),
+                ..Default::default()
+            };
+            assert!(matches!(
+                upload_encrypted(DATA.as_bytes(), FILE_NAME, Some(&opts)),
+                Err(EncryptionError::UnencryptedMetadata)
+            ));
+
+            // Download
+            let data_returned = download_decrypted(DATA_LINK, &key_returned, None).unwrap();
+            assert_eq!(data_returned, str_to_bytes(DATA));
+        })
+    }
+}
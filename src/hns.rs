@@ -0,0 +1,256 @@
+//! Handshake (HNS) functions.
+
+use crate::encoding::decode_hex_to_bytes;
+use crate::request::{execute_get, read_body, CommonOptions, RequestError};
+use crate::skylink::{new_ed25519_public_key, new_skylink_v2, parse_skylink};
+use crate::util::{
+    format_skylink, make_url, str_to_bytes, trim_prefix, URI_HANDSHAKE_PREFIX, URI_SKYNET_PREFIX,
+};
+
+use serde::Deserialize;
+use sp_std::{prelude::Vec, str};
+
+const ED25519_PREFIX: &str = "ed25519:";
+
+/// HNS error.
+#[derive(Debug)]
+pub enum HnsError {
+    /// The domain did not resolve to a skylink or registry entry.
+    InvalidResolution,
+    /// JSON error.
+    JsonError(serde_json::Error),
+    /// Request error.
+    RequestError(RequestError),
+    /// UTF8 error.
+    Utf8Error(str::Utf8Error),
+}
+
+impl From<serde_json::Error> for HnsError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::JsonError(err)
+    }
+}
+
+impl From<RequestError> for HnsError {
+    fn from(err: RequestError) -> Self {
+        Self::RequestError(err)
+    }
+}
+
+impl From<str::Utf8Error> for HnsError {
+    fn from(err: str::Utf8Error) -> Self {
+        Self::Utf8Error(err)
+    }
+}
+
+/// HNS options.
+#[derive(Debug)]
+pub struct HnsOptions<'a> {
+    /// Common options.
+    pub common: CommonOptions<'a>,
+    /// The endpoint to contact to download from a domain.
+    pub endpoint_download_hns: &'a str,
+    /// The endpoint to contact to resolve a domain.
+    pub endpoint_resolve_hns: &'a str,
+}
+
+impl Default for HnsOptions<'_> {
+    fn default() -> Self {
+        Self {
+            common: Default::default(),
+            endpoint_download_hns: "/hns",
+            endpoint_resolve_hns: "/hnsres",
+        }
+    }
+}
+
+// A domain resolves to either a skylink or a registry entry.
+#[derive(Deserialize)]
+struct ResolveHnsResponse<'a> {
+    #[serde(borrow)]
+    skylink: Option<&'a str>,
+    #[serde(borrow)]
+    registry: Option<ResolveHnsRegistry<'a>>,
+}
+
+#[derive(Deserialize)]
+struct ResolveHnsRegistry<'a> {
+    publickey: &'a str,
+    // Hex-encoded hash of the data key.
+    datakey: &'a str,
+}
+
+/// Resolves the Handshake `domain` to the skylink in its TXT record. A domain pointing to a
+/// registry entry resolves to its entry link. Returns the skylink with the `sia://` prefix.
+pub fn resolve_hns(domain: &str, opts: Option<&HnsOptions>) -> Result<Vec<u8>, HnsError> {
+    let default = Default::default();
+    let opts = opts.unwrap_or(&default);
+
+    let domain = trim_prefix(domain, URI_HANDSHAKE_PREFIX);
+
+    let url = make_url(&[opts.common.portal_url, opts.endpoint_resolve_hns, domain]);
+
+    let mut response = execute_get(str::from_utf8(&url)?, &opts.common)?;
+    let resp_bytes = read_body(&mut response, &opts.common)?;
+    let resolve_response: ResolveHnsResponse = serde_json::from_slice(&resp_bytes)?;
+
+    match resolve_response {
+        ResolveHnsResponse {
+            skylink: Some(skylink),
+            ..
+        } => {
+            let skylink = trim_prefix(skylink, URI_SKYNET_PREFIX);
+            parse_skylink(skylink).ok_or(HnsError::InvalidResolution)?;
+            Ok(format_skylink(&str_to_bytes(skylink)))
+        }
+        ResolveHnsResponse {
+            registry: Some(registry),
+            ..
+        } => {
+            let public_key = trim_prefix(registry.publickey, ED25519_PREFIX);
+            // Check the keys before decoding them, as decoding panics on invalid hex.
+            if !is_hex_hash(public_key) || !is_hex_hash(registry.datakey) {
+                return Err(HnsError::InvalidResolution);
+            }
+
+            let sia_public_key = new_ed25519_public_key(public_key);
+            let tweak = decode_hex_to_bytes(registry.datakey);
+            let skylink = new_skylink_v2(sia_public_key, &tweak).to_string();
+            Ok(format_skylink(&skylink))
+        }
+        _ => Err(HnsError::InvalidResolution),
+    }
+}
+
+/// Downloads the file at `path` from the Handshake `domain`. An empty `path` downloads the
+/// default path.
+pub fn download_hns(
+    domain: &str,
+    path: &str,
+    opts: Option<&HnsOptions>,
+) -> Result<Vec<u8>, HnsError> {
+    let default = Default::default();
+    let opts = opts.unwrap_or(&default);
+
+    let domain = trim_prefix(domain, URI_HANDSHAKE_PREFIX);
+
+    let url = make_url(&[
+        opts.common.portal_url,
+        opts.endpoint_download_hns,
+        domain,
+        path,
+    ]);
+
+    let mut response = execute_get(str::from_utf8(&url)?, &opts.common)?;
+
+    Ok(read_body(&mut response, &opts.common)?)
+}
+
+/// Checks whether `s` is a hex-encoded 32-byte hash or key.
+fn is_hex_hash(s: &str) -> bool {
+    s.len() == 64 && s.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::get_entry_link;
+
+    use sp_core::offchain::{testing, OffchainWorkerExt};
+    use sp_io::TestExternalities;
+
+    const DOMAIN: &str = "foo";
+    const DATA_LINK: &str = "MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ";
+    const EXPECTED_DATA_LINK: &str = "sia://MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ";
+    // The data key is the hash of "app".
+    const REGISTRY_JSON: &str = "{\"registry\": {\"publickey\": \"ed25519:658b900df55e983ce85f3f9fb2a088d568ab514e7bbda51cfbfb16ea945378d9\", \"datakey\": \"7c96a0537ab2aaac9cfe0eca217732f4e10791625b4ab4c17e4d91c8078713b9\"}}";
+
+    #[test]
+    fn should_resolve_hns() {
+        let (offchain, state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainWorkerExt::new(offchain));
+
+        // Add expected requests.
+        state.write().expect_request(testing::PendingRequest {
+            method: "GET".into(),
+            uri: "https://siasky.net/hnsres/foo".into(),
+            response: Some(str_to_bytes(
+                "{\"skylink\": \"sia://MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ\"}",
+            )),
+            sent: true,
+            ..Default::default()
+        });
+        state.write().expect_request(testing::PendingRequest {
+            method: "GET".into(),
+            uri: "https://siasky.net/hnsres/foo".into(),
+            response: Some(str_to_bytes(REGISTRY_JSON)),
+            sent: true,
+            ..Default::default()
+        });
+        state.write().expect_request(testing::PendingRequest {
+            method: "GET".into(),
+            uri: "https://siasky.net/hnsres/foo".into(),
+            response: Some(str_to_bytes("{\"skylink\": \"foo\"}")),
+            sent: true,
+            ..Default::default()
+        });
+
+        t.execute_with(|| {
+            // Resolve to a data link.
+            let skylink_returned = resolve_hns(DOMAIN, None).unwrap();
+            assert_eq!(skylink_returned, str_to_bytes(EXPECTED_DATA_LINK));
+
+            // Resolve to an entry link.
+            let skylink_returned = resolve_hns("hns://foo", None).unwrap();
+            let expected_entry_link = get_entry_link(
+                "658b900df55e983ce85f3f9fb2a088d568ab514e7bbda51cfbfb16ea945378d9",
+                "app",
+                None,
+            )
+            .unwrap();
+            assert_eq!(skylink_returned, expected_entry_link);
+
+            // Resolve to an invalid skylink.
+            let result = resolve_hns(DOMAIN, None);
+            assert!(matches!(result, Err(HnsError::InvalidResolution)));
+        })
+    }
+
+    #[test]
+    fn should_download_hns() {
+        const JWT_COOKIE: &str = "MTYz...==";
+
+        let (offchain, state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainWorkerExt::new(offchain));
+
+        // Add expected requests.
+        state.write().expect_request(testing::PendingRequest {
+            method: "GET".into(),
+            uri: "https://siasky.net/hns/foo/config/app.json".into(),
+            headers: vec![("Cookie".to_owned(), JWT_COOKIE.to_owned())],
+            response: Some(str_to_bytes("{}")),
+            response_headers: vec![("Skynet-Skylink".to_owned(), DATA_LINK.to_owned())],
+            sent: true,
+            ..Default::default()
+        });
+
+        t.execute_with(|| {
+            let data_returned = download_hns(
+                DOMAIN,
+                "/config/app.json",
+                Some(&HnsOptions {
+                    common: CommonOptions {
+                        custom_cookie: Some(JWT_COOKIE),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }),
+            )
+            .unwrap();
+
+            assert_eq!(data_returned, str_to_bytes("{}"));
+        })
+    }
+}
@@ -6,23 +6,61 @@
 
 // TODO: Consolidate all error types into a single crate-wide error type.
 
+mod cache;
 mod crypto;
 mod download;
 mod encoding;
+mod encryption;
+mod hns;
+mod metadata;
+mod mime;
 mod pin;
 mod registry;
+mod repin;
 mod request;
 mod skylink;
+mod tar;
+mod tus;
 mod upload;
 mod util;
 
+pub use cache::{clear_upload_cache, invalidate_upload_cache, UploadCacheOptions};
 pub use crypto::{Signature, HASH_LENGTH, PRIVATE_KEY_LENGTH, PUBLIC_KEY_LENGTH, SIGNATURE_LENGTH};
-pub use download::{download_bytes, DownloadError, DownloadOptions};
-pub use pin::{pin_skylink, PinError};
+pub use download::{
+    clear_local_storage_download, download, download_bytes, download_bytes_with_metadata,
+    download_json, download_range, download_tar, download_to_local_storage, get_file_size,
+    get_local_storage_chunk, get_local_storage_manifest, skylink_exists, DownloadError,
+    DownloadOptions, DownloadRange, DownloadResponse, DownloadToLocalStorageOptions, DownloadedTar,
+    LocalStorageManifest, DEFAULT_LOCAL_STORAGE_CHUNK_SIZE, DEFAULT_MAX_JSON_SIZE,
+};
+pub use encryption::{
+    download_decrypted, upload_encrypted, EncryptionError, EncryptionKey, ENCRYPTION_KEY_LENGTH,
+};
+pub use hns::{download_hns, resolve_hns, HnsError, HnsOptions};
+pub use metadata::{get_metadata, MetadataError, MetadataOptions, SkyfileMetadata, SkyfileSubfile};
+pub use pin::{
+    is_skylink_pinned, pin_skylink, pin_skylink_quorum, pin_skylinks, unpin_skylink, PinError,
+    PinOptions, PinResult, QuorumPinError, QuorumPinOptions,
+};
 pub use registry::{
     get_entry, get_entry_link, set_data_link, set_entry, set_entry_data, GetEntryError,
     GetEntryOptions, RegistryEntry, SetEntryError, SetEntryOptions, SignedRegistryEntry,
 };
-pub use request::{CommonOptions, RequestError, DEFAULT_PORTAL_URL};
-pub use upload::{upload_bytes, UploadError, UploadOptions};
-pub use util::URI_SKYNET_PREFIX;
+pub use repin::{
+    get_repin_schedule, run_repin, schedule_repin, unschedule_repin, RepinEntry, RepinError,
+    RepinOptions, RepinOutcome,
+};
+pub use request::{
+    CommonOptions, PortalError, RequestError, DEFAULT_PORTAL_URL, MAX_PORTAL_ERROR_MESSAGE_LENGTH,
+};
+pub use tar::{read_tar, read_tar_entries, TarEntry, TarError, TarFile};
+pub use tus::{
+    clear_large_upload, upload_large_bytes, LargeUploadOptions, LargeUploadStatus,
+    DEFAULT_TUS_CHUNK_SIZE,
+};
+pub use upload::{
+    get_offchain_index_skylink_key, upload_bytes, upload_chunks, upload_directory,
+    upload_from_offchain_index, UploadError, UploadFromOffchainIndexOptions, UploadOptions,
+    UploadResult, CUSTOM_METADATA_FIELD_NAME,
+};
+pub use util::{URI_HANDSHAKE_PREFIX, URI_SKYNET_PREFIX};
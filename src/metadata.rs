@@ -0,0 +1,313 @@
+//! Metadata functions.
+
+use crate::request::{execute_get, find_header, read_body, CommonOptions, RequestError};
+use crate::util::{de_string_to_bytes, make_url, str_to_bytes, trim_prefix, URI_SKYNET_PREFIX};
+
+use serde::Deserialize;
+use sp_std::{collections::btree_map::BTreeMap, prelude::Vec, str};
+
+/// The response header holding the skyfile metadata.
+const FILE_METADATA_HEADER: &str = "Skynet-File-Metadata";
+
+/// Metadata error.
+#[derive(Debug)]
+pub enum MetadataError {
+    /// JSON error.
+    JsonError(serde_json::Error),
+    /// The portal returned no metadata.
+    MissingMetadata,
+    /// Request error.
+    RequestError(RequestError),
+    /// UTF8 error.
+    Utf8Error(str::Utf8Error),
+}
+
+impl From<serde_json::Error> for MetadataError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::JsonError(err)
+    }
+}
+
+impl From<RequestError> for MetadataError {
+    fn from(err: RequestError) -> Self {
+        Self::RequestError(err)
+    }
+}
+
+impl From<str::Utf8Error> for MetadataError {
+    fn from(err: str::Utf8Error) -> Self {
+        Self::Utf8Error(err)
+    }
+}
+
+/// Metadata options.
+#[derive(Debug)]
+pub struct MetadataOptions<'a> {
+    /// Common options.
+    pub common: CommonOptions<'a>,
+    /// The endpoint to contact.
+    pub endpoint_metadata: &'a str,
+}
+
+impl Default for MetadataOptions<'_> {
+    fn default() -> Self {
+        Self {
+            common: Default::default(),
+            endpoint_metadata: "/skynet/metadata",
+        }
+    }
+}
+
+/// Skyfile metadata.
+#[derive(Debug, PartialEq)]
+pub struct SkyfileMetadata {
+    /// The filename of the file, or the name of the directory.
+    pub filename: Vec<u8>,
+    /// The total length of the file or directory.
+    pub length: u64,
+    /// The content type of a single file. Not set for directories.
+    pub content_type: Option<Vec<u8>>,
+    /// The files in the skyfile, sorted by path. A single file has one subfile.
+    pub subfiles: Vec<SkyfileSubfile>,
+    /// The path served when the skylink is accessed without a path.
+    pub default_path: Option<Vec<u8>>,
+    /// Whether the directory itself is served instead of a default path.
+    pub disable_default_path: bool,
+}
+
+/// A file in a skyfile.
+#[derive(Debug, PartialEq)]
+pub struct SkyfileSubfile {
+    /// The path of the file within the skyfile.
+    pub path: Vec<u8>,
+    /// The filename of the file.
+    pub filename: Vec<u8>,
+    /// The content type of the file.
+    pub content_type: Vec<u8>,
+    /// The offset of the file within the skyfile.
+    pub offset: u64,
+    /// The length of the file.
+    pub length: u64,
+}
+
+// The strings are copied instead of borrowed, as filenames can contain escapes.
+#[derive(Deserialize)]
+struct MetadataResponse {
+    #[serde(deserialize_with = "de_string_to_bytes")]
+    filename: Vec<u8>,
+    #[serde(default)]
+    length: u64,
+    #[serde(default)]
+    subfiles: BTreeMap<OwnedStr, SubfileResponse>,
+    #[serde(default, rename = "defaultpath")]
+    default_path: Option<OwnedStr>,
+    #[serde(default, rename = "disabledefaultpath")]
+    disable_default_path: bool,
+}
+
+#[derive(Deserialize)]
+struct SubfileResponse {
+    #[serde(deserialize_with = "de_string_to_bytes")]
+    filename: Vec<u8>,
+    #[serde(deserialize_with = "de_string_to_bytes", rename = "contenttype")]
+    content_type: Vec<u8>,
+    #[serde(default)]
+    offset: u64,
+    len: u64,
+}
+
+/// A string deserialized to bytes, for map keys and optional fields.
+#[derive(Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+struct OwnedStr(#[serde(deserialize_with = "de_string_to_bytes")] Vec<u8>);
+
+/// Gets the metadata of the file or directory at the given `skylink`, without downloading its
+/// contents.
+pub fn get_metadata(
+    skylink: &str,
+    opts: Option<&MetadataOptions>,
+) -> Result<SkyfileMetadata, MetadataError> {
+    let default = Default::default();
+    let opts = opts.unwrap_or(&default);
+
+    // TODO: Implement full skylink parsing.
+    let skylink = trim_prefix(skylink, URI_SKYNET_PREFIX);
+
+    let url = make_url(&[opts.common.portal_url, opts.endpoint_metadata, skylink]);
+
+    let mut response = execute_get(str::from_utf8(&url)?, &opts.common)?;
+
+    // Older portals only return the metadata in a header.
+    let header = find_header(response.headers(), FILE_METADATA_HEADER).map(str_to_bytes);
+    let body = read_body(&mut response, &opts.common)?;
+    let metadata_bytes = if !body.is_empty() {
+        body
+    } else {
+        header.ok_or(MetadataError::MissingMetadata)?
+    };
+
+    let metadata: MetadataResponse = serde_json::from_slice(&metadata_bytes)?;
+
+    Ok(metadata.into())
+}
+
+impl From<MetadataResponse> for SkyfileMetadata {
+    fn from(metadata: MetadataResponse) -> Self {
+        let subfiles = metadata
+            .subfiles
+            .into_iter()
+            .map(|(path, subfile)| SkyfileSubfile {
+                path: path.0,
+                filename: subfile.filename,
+                content_type: subfile.content_type,
+                offset: subfile.offset,
+                length: subfile.len,
+            })
+            .collect::<Vec<_>>();
+
+        // Only a single file has a content type of its own.
+        let content_type = match &subfiles[..] {
+            [subfile] if subfile.path == metadata.filename => Some(subfile.content_type.clone()),
+            _ => None,
+        };
+
+        Self {
+            filename: metadata.filename,
+            length: metadata.length,
+            content_type,
+            subfiles,
+            default_path: metadata.default_path.map(|default_path| default_path.0),
+            disable_default_path: metadata.disable_default_path,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sp_core::offchain::{testing, OffchainWorkerExt};
+    use sp_io::TestExternalities;
+
+    const DATA_LINK: &str = "MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ";
+    const FILE_METADATA: &str = "{\"filename\":\"barfile.txt\",\"length\":3,\"mode\":420,\"subfiles\":{\"barfile.txt\":{\"filename\":\"barfile.txt\",\"contenttype\":\"text/plain\",\"len\":3,\"mode\":420}}}";
+    const DIRECTORY_METADATA: &str = "{\"filename\":\"site\",\"length\":15,\"subfiles\":{\"index.html\":{\"filename\":\"index.html\",\"contenttype\":\"text/html\",\"offset\":5,\"len\":10},\"app.js\":{\"filename\":\"app.js\",\"contenttype\":\"text/javascript\",\"len\":5}},\"defaultpath\":\"/index.html\"}";
+
+    #[test]
+    fn should_get_file_metadata_from_header() {
+        let (offchain, state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainWorkerExt::new(offchain));
+
+        // Add expected request.
+        state.write().expect_request(testing::PendingRequest {
+            method: "GET".into(),
+            uri:
+                "https://siasky.net/skynet/metadata/MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ"
+                    .into(),
+            response: Some(Vec::new()),
+            response_headers: vec![("Skynet-File-Metadata".to_owned(), FILE_METADATA.to_owned())],
+            sent: true,
+            ..Default::default()
+        });
+
+        t.execute_with(|| {
+            let metadata_returned = get_metadata(DATA_LINK, None).unwrap();
+
+            assert_eq!(
+                metadata_returned,
+                SkyfileMetadata {
+                    filename: str_to_bytes("barfile.txt"),
+                    length: 3,
+                    content_type: Some(str_to_bytes("text/plain")),
+                    subfiles: vec![SkyfileSubfile {
+                        path: str_to_bytes("barfile.txt"),
+                        filename: str_to_bytes("barfile.txt"),
+                        content_type: str_to_bytes("text/plain"),
+                        offset: 0,
+                        length: 3,
+                    }],
+                    default_path: None,
+                    disable_default_path: false,
+                }
+            );
+        })
+    }
+
+    #[test]
+    fn should_get_metadata_with_escaped_filename() {
+        // Go escapes `&`, `<` and `>`.
+        const ESCAPED_METADATA: &str = r#"{"filename":"a\u0026b \"c\".txt","length":3,"subfiles":{"a\u0026b \"c\".txt":{"filename":"a\u0026b \"c\".txt","contenttype":"text/plain","len":3}},"defaultpath":"/\u003cindex\u003e.html"}"#;
+
+        let (offchain, state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainWorkerExt::new(offchain));
+
+        // Add expected request.
+        state.write().expect_request(testing::PendingRequest {
+            method: "GET".into(),
+            uri:
+                "https://siasky.net/skynet/metadata/MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ"
+                    .into(),
+            response: Some(str_to_bytes(ESCAPED_METADATA)),
+            sent: true,
+            ..Default::default()
+        });
+
+        t.execute_with(|| {
+            let metadata_returned = get_metadata(DATA_LINK, None).unwrap();
+
+            assert_eq!(metadata_returned.filename, str_to_bytes("a&b \"c\".txt"));
+            assert_eq!(
+                metadata_returned.content_type,
+                Some(str_to_bytes("text/plain"))
+            );
+            assert_eq!(
+                metadata_returned.subfiles[0].path,
+                str_to_bytes("a&b \"c\".txt")
+            );
+            assert_eq!(
+                metadata_returned.default_path,
+                Some(str_to_bytes("/<index>.html"))
+            );
+        })
+    }
+
+    #[test]
+    fn should_get_directory_metadata() {
+        let (offchain, state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainWorkerExt::new(offchain));
+
+        // Add expected request.
+        state.write().expect_request(testing::PendingRequest {
+            method: "GET".into(),
+            uri:
+                "https://siasky.net/skynet/metadata/MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ"
+                    .into(),
+            response: Some(str_to_bytes(DIRECTORY_METADATA)),
+            sent: true,
+            ..Default::default()
+        });
+
+        t.execute_with(|| {
+            let metadata_returned =
+                get_metadata(&format!("{}{}", URI_SKYNET_PREFIX, DATA_LINK), None).unwrap();
+
+            assert_eq!(metadata_returned.filename, str_to_bytes("site"));
+            assert_eq!(metadata_returned.length, 15);
+            assert_eq!(metadata_returned.content_type, None);
+            assert_eq!(
+                metadata_returned.default_path,
+                Some(str_to_bytes("/index.html"))
+            );
+            assert_eq!(
+                metadata_returned
+                    .subfiles
+                    .iter()
+                    .map(|subfile| (&subfile.path[..], subfile.offset, subfile.length))
+                    .collect::<Vec<_>>(),
+                vec![(&b"app.js"[..], 0, 5), (&b"index.html"[..], 5, 10)]
+            );
+        })
+    }
+}
@@ -0,0 +1,89 @@
+//! MIME type detection.
+
+/// The MIME type used when no other type could be detected.
+pub const DEFAULT_MIME_TYPE: &str = "application/octet-stream";
+
+/// Known file extensions and their MIME types.
+const MIME_TYPES: &[(&str, &str)] = &[
+    ("avif", "image/avif"),
+    ("bmp", "image/bmp"),
+    ("css", "text/css"),
+    ("csv", "text/csv"),
+    ("gif", "image/gif"),
+    ("gz", "application/gzip"),
+    ("htm", "text/html"),
+    ("html", "text/html"),
+    ("ico", "image/vnd.microsoft.icon"),
+    ("jpeg", "image/jpeg"),
+    ("jpg", "image/jpeg"),
+    ("js", "text/javascript"),
+    ("json", "application/json"),
+    ("jsonld", "application/ld+json"),
+    ("md", "text/markdown"),
+    ("mjs", "text/javascript"),
+    ("mp3", "audio/mpeg"),
+    ("mp4", "video/mp4"),
+    ("oga", "audio/ogg"),
+    ("ogg", "audio/ogg"),
+    ("ogv", "video/ogg"),
+    ("otf", "font/otf"),
+    ("pdf", "application/pdf"),
+    ("png", "image/png"),
+    ("svg", "image/svg+xml"),
+    ("tar", "application/x-tar"),
+    ("ttf", "font/ttf"),
+    ("txt", "text/plain"),
+    ("wasm", "application/wasm"),
+    ("wav", "audio/wav"),
+    ("weba", "audio/webm"),
+    ("webm", "video/webm"),
+    ("webmanifest", "application/manifest+json"),
+    ("webp", "image/webp"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("xml", "application/xml"),
+    ("yaml", "application/yaml"),
+    ("yml", "application/yaml"),
+    ("zip", "application/zip"),
+];
+
+/// Returns the MIME type for the given `path` based on its file extension, or
+/// [`DEFAULT_MIME_TYPE`] if the extension is missing or unknown.
+pub fn get_mime_type(path: &str) -> &'static str {
+    // Only look at the last path component so that dots in directory names are ignored.
+    let filename = path.rsplit('/').next().unwrap_or(path);
+
+    let extension = match filename.rsplit_once('.') {
+        // A leading dot marks a hidden file, not an extension.
+        Some((name, extension)) if !name.is_empty() => extension,
+        _ => return DEFAULT_MIME_TYPE,
+    };
+
+    MIME_TYPES
+        .iter()
+        .find(|(ext, _)| ext.eq_ignore_ascii_case(extension))
+        .map(|(_, mime)| *mime)
+        .unwrap_or(DEFAULT_MIME_TYPE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_get_mime_type_from_extension() {
+        assert_eq!(get_mime_type("index.html"), "text/html");
+        assert_eq!(get_mime_type("data.JSON"), "application/json");
+        assert_eq!(get_mime_type("assets/app.min.js"), "text/javascript");
+        assert_eq!(get_mime_type("archive.tar.gz"), "application/gzip");
+    }
+
+    #[test]
+    fn should_fall_back_to_default_mime_type() {
+        assert_eq!(get_mime_type("barfile"), DEFAULT_MIME_TYPE);
+        assert_eq!(get_mime_type("file.unknown"), DEFAULT_MIME_TYPE);
+        assert_eq!(get_mime_type(".hidden"), DEFAULT_MIME_TYPE);
+        assert_eq!(get_mime_type("dir.d/file"), DEFAULT_MIME_TYPE);
+        assert_eq!(get_mime_type(""), DEFAULT_MIME_TYPE);
+    }
+}
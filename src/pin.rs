@@ -1,19 +1,50 @@
 //! Pin functions.
 
-use crate::request::{execute_get, CommonOptions, RequestError};
-use crate::util::{make_url, str_to_bytes, URI_SKYNET_PREFIX};
+use crate::request::{
+    add_headers, execute_get, execute_method, find_header, read_body, CommonOptions, PortalError,
+    RequestError,
+};
+use crate::skylink::is_skylink_v2;
+use crate::util::{
+    concat_strs, format_skylink, make_url, str_to_bytes, trim_prefix, URI_SKYNET_PREFIX,
+};
 
+use serde::Deserialize;
+use sp_io::offchain;
+use sp_runtime::offchain::{self as rt_offchain, http};
 use sp_std::{str, vec::Vec};
 
+/// The response header holding the pinned skylink.
+const SKYLINK_HEADER: &str = "Skynet-Skylink";
+
+/// The message the account API answers with, along with a 404, for skylinks the account has not
+/// pinned. The API gives no error code, so this message is the only way to tell its 404 from a 404
+/// for a wrong URL. It is matched ignoring case; should the API reword it, negative answers are
+/// reported as `UnexpectedStatus` errors rather than as pinned or not.
+const UNKNOWN_SKYLINK_MESSAGE: &str = "skylink not found";
+
 /// Pin error.
 #[derive(Debug)]
 pub enum PinError {
+    /// The skylink is an entry link, which can't be pinned itself, and resolving entry links was
+    /// not enabled.
+    CannotPinEntryLink,
+    /// The portal answered with a skylink other than the one asked about.
+    InvalidResponse,
+    /// JSON error.
+    JsonError(serde_json::Error),
+    /// The portal did not return the given header.
+    MissingHeader(&'static str),
     /// Request error.
     RequestError(RequestError),
     /// UTF8 error.
     Utf8Error(str::Utf8Error),
-    /// Validation error.
-    ValidationError(Vec<u8>),
+}
+
+impl From<serde_json::Error> for PinError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::JsonError(err)
+    }
 }
 
 impl From<RequestError> for PinError {
@@ -33,47 +64,360 @@ impl From<str::Utf8Error> for PinError {
 pub struct PinOptions<'a> {
     /// Common options.
     pub common: CommonOptions<'a>,
+    /// Optional URL of the account API, used to query pin status. If not given, it is the
+    /// `account.` subdomain of the portal URL, where portals serve it.
+    pub account_url: Option<&'a str>,
     /// The endpoint to contact.
     pub endpoint_pin: &'a str,
+    /// The endpoint to contact for unpinning.
+    pub endpoint_unpin: &'a str,
+    /// The account API endpoint listing the uploads of the account, used to query pin status.
+    pub endpoint_pin_status: &'a str,
+    /// The endpoint to contact for resolving entry links.
+    pub endpoint_resolve: &'a str,
+    /// Whether to resolve entry links and pin the data link they point to, instead of failing with
+    /// `PinError::CannotPinEntryLink`.
+    pub resolve_entry_links: bool,
 }
 
 impl Default for PinOptions<'_> {
     fn default() -> Self {
         Self {
             common: Default::default(),
+            account_url: None,
             endpoint_pin: "/skynet/pin",
+            endpoint_unpin: "/skynet/unpin",
+            endpoint_pin_status: "/api/user/uploads",
+            endpoint_resolve: "/skynet/resolve",
+            resolve_entry_links: false,
+        }
+    }
+}
+
+/// Quorum pin options.
+#[derive(Debug)]
+pub struct QuorumPinOptions<'a> {
+    /// The number of portals that must confirm the pin. Defaults to a majority of the portals.
+    pub quorum: Option<usize>,
+    /// The endpoint to contact on each portal.
+    pub endpoint_pin: &'a str,
+    /// The endpoint to contact for resolving entry links.
+    pub endpoint_resolve: &'a str,
+    /// Whether to resolve entry links and pin the data link they point to, instead of failing with
+    /// `PinError::CannotPinEntryLink`. The first portal to resolve the entry link is used.
+    pub resolve_entry_links: bool,
+    /// The number of milliseconds to wait for the portals to confirm the pin.
+    pub timeout: u64,
+}
+
+impl Default for QuorumPinOptions<'_> {
+    fn default() -> Self {
+        Self {
+            quorum: None,
+            endpoint_pin: "/skynet/pin",
+            endpoint_resolve: "/skynet/resolve",
+            resolve_entry_links: false,
+            // Keeps the offchain worker execution time reasonable.
+            timeout: 3000,
+        }
+    }
+}
+
+/// Quorum pin error.
+#[derive(Debug)]
+pub enum QuorumPinError {
+    /// The quorum was zero or larger than the number of portals.
+    InvalidQuorum,
+    /// The skylink could not be pinned on any portal, e.g. because it is an entry link that could
+    /// not be resolved.
+    PinError(PinError),
+    /// Fewer portals than the quorum confirmed the pin. Holds the outcome for each portal.
+    QuorumNotReached(Vec<Result<PinResult, PinError>>),
+}
+
+impl From<PinError> for QuorumPinError {
+    fn from(err: PinError) -> Self {
+        Self::PinError(err)
+    }
+}
+
+/// The result of pinning a skylink.
+#[derive(Debug, PartialEq)]
+pub struct PinResult {
+    /// The skylink pinned by the portal, with the `sia://` prefix.
+    pub skylink: Vec<u8>,
+    /// The entry link that was resolved to the pinned skylink, if an entry link was given, with
+    /// the `sia://` prefix.
+    pub entry_link: Option<Vec<u8>>,
+}
+
+#[derive(Deserialize)]
+struct ResolveResponse<'a> {
+    skylink: &'a str,
+}
+
+#[derive(Deserialize)]
+struct PinStatusResponse<'a> {
+    skylink: &'a str,
+}
+
+/// Re-pins the given `skylink`. Entry links are resolved and the data link they currently point to
+/// is pinned if enabled in the options.
+pub fn pin_skylink(skylink: &str, opts: Option<&PinOptions>) -> Result<PinResult, PinError> {
+    let default = Default::default();
+    let opts = opts.unwrap_or(&default);
+
+    // TODO: Implement full skylink parsing.
+    let skylink = trim_prefix(skylink, URI_SKYNET_PREFIX);
+
+    if !is_skylink_v2(skylink) {
+        let url = make_url(&[opts.common.portal_url, opts.endpoint_pin, skylink]);
+
+        let mut response = execute_method(http::Method::Post, str::from_utf8(&url)?, &opts.common)?;
+
+        return read_pin_result(&mut response);
+    }
+
+    // Pinning an entry link would only pin the registry entry, not the data it points to.
+    if !opts.resolve_entry_links {
+        return Err(PinError::CannotPinEntryLink);
+    }
+
+    let data_link = resolve_entry_link(skylink, opts.endpoint_resolve, &opts.common)?;
+
+    let mut result = pin_skylink(str::from_utf8(&data_link)?, Some(opts))?;
+    result.entry_link = Some(format_skylink(&str_to_bytes(skylink)));
+
+    Ok(result)
+}
+
+/// Unpins the given `skylink`, so the portal no longer keeps it alive for this account.
+pub fn unpin_skylink(skylink: &str, opts: Option<&PinOptions>) -> Result<(), PinError> {
+    let default = Default::default();
+    let opts = opts.unwrap_or(&default);
+
+    // TODO: Implement full skylink parsing.
+    let skylink = trim_prefix(skylink, URI_SKYNET_PREFIX);
+
+    let url = make_url(&[opts.common.portal_url, opts.endpoint_unpin, skylink]);
+
+    execute_method(http::Method::Post, str::from_utf8(&url)?, &opts.common)?;
+
+    Ok(())
+}
+
+/// Checks whether the given `skylink` is pinned by the account authenticated in the common
+/// options, by asking the account API at `account_url`.
+pub fn is_skylink_pinned(skylink: &str, opts: Option<&PinOptions>) -> Result<bool, PinError> {
+    let default = Default::default();
+    let opts = opts.unwrap_or(&default);
+
+    // TODO: Implement full skylink parsing.
+    let skylink = trim_prefix(skylink, URI_SKYNET_PREFIX);
+
+    // The API key is only sent to the account API of the portal it was given for.
+    let account_url = match opts.account_url {
+        Some(account_url) => str_to_bytes(account_url),
+        None => make_account_url(opts.common.portal_url),
+    };
+    let url = make_url(&[
+        str::from_utf8(&account_url)?,
+        opts.endpoint_pin_status,
+        skylink,
+    ]);
+
+    // The account API answers 404 with its own message for skylinks the account has not pinned.
+    // Other 404s, e.g. from a wrong URL, are errors rather than a negative answer.
+    let mut response = match execute_get(str::from_utf8(&url)?, &opts.common) {
+        Ok(response) => response,
+        Err(RequestError::UnexpectedStatus(PortalError {
+            status: 404,
+            message: Some(message),
+            ..
+        })) if message.eq_ignore_ascii_case(UNKNOWN_SKYLINK_MESSAGE.as_bytes()) => {
+            return Ok(false)
         }
+        Err(e) => return Err(e.into()),
+    };
+
+    // The upload is returned when pinned.
+    let body = read_body(&mut response, &opts.common)?;
+    let status: PinStatusResponse = serde_json::from_slice(&body)?;
+    if trim_prefix(status.skylink, URI_SKYNET_PREFIX) != skylink {
+        return Err(PinError::InvalidResponse);
     }
+
+    Ok(true)
 }
 
-/// Re-pins the given `skylink`.
-pub fn pin_skylink(skylink: &str, opts: Option<&PinOptions>) -> Result<Vec<u8>, PinError> {
+/// Re-pins each of the given `skylinks` in turn. Returns a result for each skylink, in the same
+/// order, so one failure does not stop the rest of the batch.
+pub fn pin_skylinks(
+    skylinks: &[&str],
+    opts: Option<&PinOptions>,
+) -> Vec<Result<PinResult, PinError>> {
+    skylinks
+        .iter()
+        .map(|skylink| pin_skylink(skylink, opts))
+        .collect()
+}
+
+/// Pins the given `skylink` on each of the `portals` concurrently, using the options of each
+/// portal for its request. Succeeds when at least the quorum of portals confirm the pin. Returns
+/// the outcome for each portal, in the same order, either way. Entry links are resolved and the
+/// data link they currently point to is pinned if enabled in the options.
+pub fn pin_skylink_quorum(
+    skylink: &str,
+    portals: &[CommonOptions],
+    opts: Option<&QuorumPinOptions>,
+) -> Result<Vec<Result<PinResult, PinError>>, QuorumPinError> {
     let default = Default::default();
     let opts = opts.unwrap_or(&default);
 
+    let quorum = opts.quorum.unwrap_or(portals.len() / 2 + 1);
+    if quorum == 0 || quorum > portals.len() {
+        return Err(QuorumPinError::InvalidQuorum);
+    }
+
     // TODO: Implement full skylink parsing.
-    let skylink = if let Some(stripped) = skylink.strip_prefix(URI_SKYNET_PREFIX) {
-        stripped
+    let skylink = trim_prefix(skylink, URI_SKYNET_PREFIX);
+
+    // Entry links are resolved once, so every portal pins the same data link.
+    let (data_link, entry_link) = if is_skylink_v2(skylink) {
+        if !opts.resolve_entry_links {
+            return Err(PinError::CannotPinEntryLink.into());
+        }
+        (
+            resolve_entry_link_on_any(skylink, portals, opts)?,
+            Some(format_skylink(&str_to_bytes(skylink))),
+        )
     } else {
-        skylink
+        (str_to_bytes(skylink), None)
     };
+    let data_link = str::from_utf8(&data_link).map_err(PinError::from)?;
 
-    let url = make_url(&[opts.common.portal_url, opts.endpoint_pin, skylink]);
+    let urls = portals
+        .iter()
+        .map(|common| make_url(&[common.portal_url, opts.endpoint_pin, data_link]))
+        .collect::<Vec<_>>();
 
-    let mut response = execute_get(str::from_utf8(&url)?, &opts.common)?;
+    let timeout = offchain::timestamp().add(rt_offchain::Duration::from_millis(opts.timeout));
 
-    let headers = response.headers();
-    let skylink = headers
-        .find("skynet-skylink")
-        .or_else(|| headers.find("Skynet-Skylink"));
+    // Send every request before waiting on any of them. Portals that have not answered by the
+    // deadline are left with a timeout error.
+    let mut results = Vec::with_capacity(portals.len());
+    let mut pending = Vec::with_capacity(portals.len());
+    let mut pending_indices = Vec::with_capacity(portals.len());
+    for (i, (url, common)) in urls.iter().zip(portals).enumerate() {
+        match send_pin_request(url, common, timeout) {
+            Ok(request) => {
+                pending.push(request);
+                pending_indices.push(i);
+                results.push(Err(RequestError::TimeoutError.into()));
+            }
+            Err(err) => results.push(Err(err)),
+        }
+    }
+
+    let responses = http::PendingRequest::try_wait_all(pending, Some(timeout));
+    for (i, response) in pending_indices.into_iter().zip(responses) {
+        results[i] = match response {
+            Ok(Ok(mut response)) if response.code < 400 => read_pin_result(&mut response),
+            Ok(Ok(response)) => Err(RequestError::UnexpectedStatus(PortalError::from_response(
+                response,
+                // The URL was checked when sending the request.
+                str::from_utf8(&urls[i]).unwrap_or_default(),
+            ))
+            .into()),
+            Ok(Err(err)) => Err(RequestError::from(err).into()),
+            Err(_) => Err(RequestError::TimeoutError.into()),
+        };
+    }
+    if entry_link.is_some() {
+        for result in results.iter_mut().flatten() {
+            result.entry_link = entry_link.clone();
+        }
+    }
 
-    if let Some(skylink) = skylink {
-        Ok(str_to_bytes(skylink))
+    let confirmations = results.iter().filter(|result| result.is_ok()).count();
+    if confirmations >= quorum {
+        Ok(results)
     } else {
-        Err(PinError::ValidationError(str_to_bytes(
-            "'skynet-skylink' header not found in response",
-        )))
+        Err(QuorumPinError::QuorumNotReached(results))
+    }
+}
+
+/// Gets the URL of the account API of the portal at `portal_url`, which is its `account.`
+/// subdomain.
+fn make_account_url(portal_url: &str) -> Vec<u8> {
+    match portal_url.split_once("://") {
+        Some((scheme, host)) => concat_strs(&[scheme, "://account.", host]),
+        None => concat_strs(&["account.", portal_url]),
+    }
+}
+
+/// Resolves the entry link `skylink` to the data link it points to.
+fn resolve_entry_link(
+    skylink: &str,
+    endpoint_resolve: &str,
+    common: &CommonOptions,
+) -> Result<Vec<u8>, PinError> {
+    let url = make_url(&[common.portal_url, endpoint_resolve, skylink]);
+
+    let mut response = execute_get(str::from_utf8(&url)?, common)?;
+    let body = read_body(&mut response, common)?;
+    let resolved: ResolveResponse = serde_json::from_slice(&body)?;
+
+    // Entry links may point to other entry links, which are not followed.
+    if is_skylink_v2(resolved.skylink) {
+        return Err(PinError::CannotPinEntryLink);
     }
+
+    Ok(str_to_bytes(resolved.skylink))
+}
+
+/// Resolves the entry link `skylink` on each of the `portals` in turn, until one succeeds. Returns
+/// the error of the last portal if none does.
+fn resolve_entry_link_on_any(
+    skylink: &str,
+    portals: &[CommonOptions],
+    opts: &QuorumPinOptions,
+) -> Result<Vec<u8>, PinError> {
+    let mut last_err = PinError::CannotPinEntryLink;
+    for common in portals {
+        match resolve_entry_link(skylink, opts.endpoint_resolve, common) {
+            Ok(data_link) => return Ok(data_link),
+            Err(err) => last_err = err,
+        }
+    }
+
+    Err(last_err)
+}
+
+/// Sends a pin request to `url` without waiting for the response.
+fn send_pin_request(
+    url: &[u8],
+    common: &CommonOptions,
+    timeout: rt_offchain::Timestamp,
+) -> Result<http::PendingRequest, PinError> {
+    let request = http::Request::get(str::from_utf8(url)?).method(http::Method::Post);
+    let request = add_headers(request, common);
+
+    Ok(request
+        .deadline(timeout)
+        .send()
+        .map_err(RequestError::from)?)
+}
+
+/// Reads the pinned skylink from a successful pin `response`.
+fn read_pin_result(response: &mut http::Response) -> Result<PinResult, PinError> {
+    let skylink = find_header(response.headers(), SKYLINK_HEADER)
+        .ok_or(PinError::MissingHeader(SKYLINK_HEADER))?;
+
+    Ok(PinResult {
+        skylink: format_skylink(&str_to_bytes(trim_prefix(skylink, URI_SKYNET_PREFIX))),
+        entry_link: None,
+    })
 }
 
 #[cfg(test)]
@@ -84,7 +428,7 @@ mod tests {
     use sp_io::TestExternalities;
 
     const DATA_LINK: &str = "MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ";
-    // const ENTRY_LINK: &str = "AQAZ1R-KcL4NO_xIVf0q8B1ngPVd6ec-Pu54O0Cto387Nw";
+    const ENTRY_LINK: &str = "AQAZ1R-KcL4NO_xIVf0q8B1ngPVd6ec-Pu54O0Cto387Nw";
 
     #[test]
     fn should_pin_data_link() {
@@ -94,7 +438,7 @@ mod tests {
 
         // Add expected request.
         state.write().expect_request(testing::PendingRequest {
-            method: "GET".into(),
+            method: "POST".into(),
             uri: "https://siasky.net/skynet/pin/MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ"
                 .into(),
             response: Some(br#""#.to_vec()),
@@ -105,34 +449,501 @@ mod tests {
 
         t.execute_with(|| {
             // Call pin_skylink.
-            let skylink_returned = pin_skylink(DATA_LINK, None).unwrap();
+            let result = pin_skylink(DATA_LINK, None).unwrap();
 
             // Check the response.
-            assert_eq!(skylink_returned, str_to_bytes(DATA_LINK));
+            assert_eq!(
+                result,
+                PinResult {
+                    skylink: format_skylink(&str_to_bytes(DATA_LINK)),
+                    entry_link: None,
+                }
+            );
+        })
+    }
+
+    #[test]
+    fn should_pin_skylinks() {
+        let (offchain, state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainWorkerExt::new(offchain));
+
+        // Add expected requests. The first portal answers with the prefix, which isn't doubled,
+        // and the second response is missing the skylink header.
+        state.write().expect_request(testing::PendingRequest {
+            method: "POST".into(),
+            uri: "https://siasky.net/skynet/pin/MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ"
+                .into(),
+            response: Some(Vec::new()),
+            response_headers: vec![(
+                "Skynet-Skylink".to_owned(),
+                format!("{}{}", URI_SKYNET_PREFIX, DATA_LINK),
+            )],
+            sent: true,
+            ..Default::default()
+        });
+        state.write().expect_request(testing::PendingRequest {
+            method: "POST".into(),
+            uri: "https://siasky.net/skynet/pin/AAA6Z7R0sjreLCr35fJKhMXuc8CE6mxRhkHQtmgtJGzqvw"
+                .into(),
+            response: Some(Vec::new()),
+            sent: true,
+            ..Default::default()
+        });
+
+        t.execute_with(|| {
+            let results = pin_skylinks(
+                &[
+                    &format!("{}{}", URI_SKYNET_PREFIX, DATA_LINK),
+                    "AAA6Z7R0sjreLCr35fJKhMXuc8CE6mxRhkHQtmgtJGzqvw",
+                ],
+                None,
+            );
+
+            assert_eq!(results.len(), 2);
+            assert_eq!(
+                results[0].as_ref().unwrap().skylink,
+                format_skylink(&str_to_bytes(DATA_LINK))
+            );
+            assert!(matches!(
+                results[1],
+                Err(PinError::MissingHeader(SKYLINK_HEADER))
+            ));
+        })
+    }
+
+    #[test]
+    fn should_unpin_skylink() {
+        let (offchain, state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainWorkerExt::new(offchain));
+
+        // Add expected request.
+        state.write().expect_request(testing::PendingRequest {
+            method: "POST".into(),
+            uri: "https://siasky.net/skynet/unpin/MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ"
+                .into(),
+            headers: vec![("Skynet-Api-Key".to_owned(), "foo".to_owned())],
+            response: Some(Vec::new()),
+            sent: true,
+            ..Default::default()
+        });
+
+        t.execute_with(|| {
+            let opts = PinOptions {
+                common: CommonOptions {
+                    skynet_api_key: Some("foo"),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+
+            unpin_skylink(DATA_LINK, Some(&opts)).unwrap();
         })
     }
 
-    // // TODO: Update test.
-    // #[test]
-    // fn should_fail_to_pin_entry_link() {
-    //     let (offchain, state) = testing::TestOffchainExt::new();
-    //     let mut t = TestExternalities::default();
-    //     t.register_extension(OffchainExt::new(offchain));
-
-    //     // Add expected request.
-    //     state.write().expect_request(testing::PendingRequest {
-    //         method: "GET".into(),
-    //         uri: "https://siasky.net/skynet/pin/MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ"
-    //             .into(),
-    //         response: Some(br#""#.to_vec()),
-    //         response_headers: vec![("Skynet-Skylink".to_owned(), DATA_LINK.to_owned())],
-    //         sent: true,
-    //         ..Default::default()
-    //     });
-
-    //     t.execute_with(|| {
-    //         // Call pin_skylink.
-    //         let skylink_returned = pin_skylink(ENTRY_LINK, &Default::default()).unwrap();
-    //     })
-    // }
+    #[test]
+    fn should_get_pin_status() {
+        let (offchain, state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainWorkerExt::new(offchain));
+
+        // Add expected request.
+        state.write().expect_request(testing::PendingRequest {
+            method: "GET".into(),
+            uri: "https://account.siasky.net/api/user/uploads/MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ"
+                .into(),
+            headers: vec![("Skynet-Api-Key".to_owned(), "foo".to_owned())],
+            response: Some(format!("{{\"skylink\":\"{}\"}}", DATA_LINK).into_bytes()),
+            sent: true,
+            ..Default::default()
+        });
+        state.write().expect_request(testing::PendingRequest {
+            method: "GET".into(),
+            uri: "https://account.siasky.net/api/user/uploads/AAA6Z7R0sjreLCr35fJKhMXuc8CE6mxRhkHQtmgtJGzqvw"
+                .into(),
+            response: Some(format!("{{\"skylink\":\"{}\"}}", DATA_LINK).into_bytes()),
+            sent: true,
+            ..Default::default()
+        });
+        state.write().expect_request(testing::PendingRequest {
+            method: "GET".into(),
+            uri: "https://account.skyportal.xyz/api/user/uploads/MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ"
+                .into(),
+            headers: vec![("Skynet-Api-Key".to_owned(), "bar".to_owned())],
+            response: Some(format!("{{\"skylink\":\"{}\"}}", DATA_LINK).into_bytes()),
+            sent: true,
+            ..Default::default()
+        });
+
+        t.execute_with(|| {
+            let opts = PinOptions {
+                common: CommonOptions {
+                    skynet_api_key: Some("foo"),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+
+            assert!(is_skylink_pinned(DATA_LINK, Some(&opts)).unwrap());
+
+            // The answer is about another skylink.
+            assert!(matches!(
+                is_skylink_pinned("AAA6Z7R0sjreLCr35fJKhMXuc8CE6mxRhkHQtmgtJGzqvw", None),
+                Err(PinError::InvalidResponse)
+            ));
+
+            // The account API of a custom portal is asked.
+            let opts = PinOptions {
+                common: CommonOptions {
+                    portal_url: "https://skyportal.xyz",
+                    skynet_api_key: Some("bar"),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            assert!(is_skylink_pinned(DATA_LINK, Some(&opts)).unwrap());
+        })
+    }
+
+    #[test]
+    fn should_only_treat_unknown_skylink_as_not_pinned() {
+        use crate::request::tests::StatusOffchainExt;
+
+        let (offchain, state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainWorkerExt::new(StatusOffchainExt::new(
+            offchain,
+            &[(0, 404), (1, 404)],
+        )));
+
+        // Add expected requests. The second answer comes from something other than the API.
+        for response in [
+            &br#"{"message":"Skylink not found"}"#[..],
+            &b"<html>404 page not found</html>"[..],
+        ] {
+            state.write().expect_request(testing::PendingRequest {
+                method: "GET".into(),
+                uri: "https://account.siasky.net/api/user/uploads/MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ"
+                    .into(),
+                response: Some(response.to_vec()),
+                sent: true,
+                ..Default::default()
+            });
+        }
+
+        t.execute_with(|| {
+            assert!(!is_skylink_pinned(DATA_LINK, None).unwrap());
+
+            assert!(matches!(
+                is_skylink_pinned(DATA_LINK, None),
+                Err(PinError::RequestError(RequestError::UnexpectedStatus(
+                    PortalError { status: 404, .. }
+                )))
+            ));
+        })
+    }
+
+    #[test]
+    fn should_pin_skylink_on_quorum_of_portals() {
+        let (offchain, state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainWorkerExt::new(offchain));
+
+        // Add expected requests. The second portal does not confirm the pin.
+        state.write().expect_request(testing::PendingRequest {
+            method: "POST".into(),
+            uri: "https://siasky.net/skynet/pin/MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ"
+                .into(),
+            headers: vec![("Skynet-Api-Key".to_owned(), "foo".to_owned())],
+            response: Some(Vec::new()),
+            response_headers: vec![("Skynet-Skylink".to_owned(), DATA_LINK.to_owned())],
+            sent: true,
+            ..Default::default()
+        });
+        state.write().expect_request(testing::PendingRequest {
+            method: "POST".into(),
+            uri: "https://skyportal.xyz/skynet/pin/MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ"
+                .into(),
+            response: Some(Vec::new()),
+            sent: true,
+            ..Default::default()
+        });
+        state.write().expect_request(testing::PendingRequest {
+            method: "POST".into(),
+            uri: "https://fileportal.org/skynet/pin/MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ"
+                .into(),
+            headers: vec![("Cookie".to_owned(), "bar".to_owned())],
+            response: Some(Vec::new()),
+            response_headers: vec![("Skynet-Skylink".to_owned(), DATA_LINK.to_owned())],
+            sent: true,
+            ..Default::default()
+        });
+
+        t.execute_with(|| {
+            let portals = [
+                CommonOptions {
+                    skynet_api_key: Some("foo"),
+                    ..Default::default()
+                },
+                CommonOptions {
+                    portal_url: "https://skyportal.xyz",
+                    ..Default::default()
+                },
+                CommonOptions {
+                    portal_url: "https://fileportal.org",
+                    custom_cookie: Some("bar"),
+                    ..Default::default()
+                },
+            ];
+
+            let results = pin_skylink_quorum(DATA_LINK, &portals, None).unwrap();
+
+            assert_eq!(results.len(), 3);
+            assert_eq!(
+                results[0].as_ref().unwrap().skylink,
+                format_skylink(&str_to_bytes(DATA_LINK))
+            );
+            assert!(matches!(
+                results[1],
+                Err(PinError::MissingHeader(SKYLINK_HEADER))
+            ));
+            assert!(results[2].is_ok());
+        })
+    }
+
+    #[test]
+    fn should_fail_to_reach_pin_quorum() {
+        let (offchain, state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainWorkerExt::new(offchain));
+
+        // Add expected requests. Only the first portal confirms the pin.
+        state.write().expect_request(testing::PendingRequest {
+            method: "POST".into(),
+            uri: "https://siasky.net/skynet/pin/MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ"
+                .into(),
+            response: Some(Vec::new()),
+            response_headers: vec![("Skynet-Skylink".to_owned(), DATA_LINK.to_owned())],
+            sent: true,
+            ..Default::default()
+        });
+        state.write().expect_request(testing::PendingRequest {
+            method: "POST".into(),
+            uri: "https://skyportal.xyz/skynet/pin/MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ"
+                .into(),
+            response: Some(Vec::new()),
+            sent: true,
+            ..Default::default()
+        });
+
+        t.execute_with(|| {
+            let portals = [
+                CommonOptions::default(),
+                CommonOptions {
+                    portal_url: "https://skyportal.xyz",
+                    ..Default::default()
+                },
+            ];
+
+            let opts = QuorumPinOptions {
+                quorum: Some(3),
+                ..Default::default()
+            };
+            assert!(matches!(
+                pin_skylink_quorum(DATA_LINK, &portals, Some(&opts)),
+                Err(QuorumPinError::InvalidQuorum)
+            ));
+
+            // A majority of two portals is both of them.
+            match pin_skylink_quorum(DATA_LINK, &portals, None) {
+                Err(QuorumPinError::QuorumNotReached(results)) => {
+                    assert!(results[0].is_ok());
+                    assert!(results[1].is_err());
+                }
+                result => panic!("unexpected result: {:?}", result),
+            }
+        })
+    }
+
+    #[test]
+    fn should_report_timeouts_of_quorum_portals() {
+        use crate::request::tests::StatusOffchainExt;
+
+        let (offchain, state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainWorkerExt::new(
+            StatusOffchainExt::new(offchain, &[]).with_deadline_reached(1),
+        ));
+
+        // Add expected requests. The second portal misses the deadline.
+        for portal_url in ["https://siasky.net", "https://skyportal.xyz"] {
+            state.write().expect_request(testing::PendingRequest {
+                method: "POST".into(),
+                uri: format!("{}/skynet/pin/{}", portal_url, DATA_LINK),
+                response: Some(Vec::new()),
+                response_headers: vec![("Skynet-Skylink".to_owned(), DATA_LINK.to_owned())],
+                sent: true,
+                ..Default::default()
+            });
+        }
+
+        t.execute_with(|| {
+            let portals = [
+                CommonOptions::default(),
+                CommonOptions {
+                    portal_url: "https://skyportal.xyz",
+                    ..Default::default()
+                },
+            ];
+            let opts = QuorumPinOptions {
+                quorum: Some(1),
+                timeout: 500,
+                ..Default::default()
+            };
+
+            let results = pin_skylink_quorum(DATA_LINK, &portals, Some(&opts)).unwrap();
+
+            assert!(results[0].is_ok());
+            assert!(matches!(
+                results[1],
+                Err(PinError::RequestError(RequestError::TimeoutError))
+            ));
+        })
+    }
+
+    #[test]
+    fn should_fail_to_pin_entry_link() {
+        let (offchain, _state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainWorkerExt::new(offchain));
+
+        t.execute_with(|| {
+            // No request is sent.
+            assert!(matches!(
+                pin_skylink(ENTRY_LINK, None),
+                Err(PinError::CannotPinEntryLink)
+            ));
+        })
+    }
+
+    #[test]
+    fn should_pin_data_link_of_entry_link() {
+        let (offchain, state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainWorkerExt::new(offchain));
+
+        // Add expected requests.
+        state.write().expect_request(testing::PendingRequest {
+            method: "GET".into(),
+            uri: "https://siasky.net/skynet/resolve/AQAZ1R-KcL4NO_xIVf0q8B1ngPVd6ec-Pu54O0Cto387Nw"
+                .into(),
+            response: Some(format!("{{\"skylink\":\"{}\"}}", DATA_LINK).into_bytes()),
+            sent: true,
+            ..Default::default()
+        });
+        state.write().expect_request(testing::PendingRequest {
+            method: "POST".into(),
+            uri: "https://siasky.net/skynet/pin/MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ"
+                .into(),
+            response: Some(Vec::new()),
+            response_headers: vec![("Skynet-Skylink".to_owned(), DATA_LINK.to_owned())],
+            sent: true,
+            ..Default::default()
+        });
+
+        t.execute_with(|| {
+            let opts = PinOptions {
+                resolve_entry_links: true,
+                ..Default::default()
+            };
+
+            let result =
+                pin_skylink(&format!("{}{}", URI_SKYNET_PREFIX, ENTRY_LINK), Some(&opts)).unwrap();
+
+            assert_eq!(
+                result,
+                PinResult {
+                    skylink: format_skylink(&str_to_bytes(DATA_LINK)),
+                    entry_link: Some(format_skylink(&str_to_bytes(ENTRY_LINK))),
+                }
+            );
+        })
+    }
+
+    #[test]
+    fn should_fail_to_pin_entry_link_on_quorum() {
+        let (offchain, _state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainWorkerExt::new(offchain));
+
+        t.execute_with(|| {
+            // No request is sent.
+            assert!(matches!(
+                pin_skylink_quorum(ENTRY_LINK, &[CommonOptions::default()], None),
+                Err(QuorumPinError::PinError(PinError::CannotPinEntryLink))
+            ));
+        })
+    }
+
+    #[test]
+    fn should_pin_data_link_of_entry_link_on_quorum() {
+        use crate::request::tests::StatusOffchainExt;
+
+        let (offchain, state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainWorkerExt::new(StatusOffchainExt::new(
+            offchain,
+            &[(0, 500)],
+        )));
+
+        // Add expected requests. The first portal fails to resolve the entry link, so the second
+        // one is used.
+        for portal_url in ["https://siasky.net", "https://skyportal.xyz"] {
+            state.write().expect_request(testing::PendingRequest {
+                method: "GET".into(),
+                uri: format!("{}/skynet/resolve/{}", portal_url, ENTRY_LINK),
+                response: Some(format!("{{\"skylink\":\"{}\"}}", DATA_LINK).into_bytes()),
+                sent: true,
+                ..Default::default()
+            });
+        }
+        for portal_url in ["https://siasky.net", "https://skyportal.xyz"] {
+            state.write().expect_request(testing::PendingRequest {
+                method: "POST".into(),
+                uri: format!("{}/skynet/pin/{}", portal_url, DATA_LINK),
+                response: Some(Vec::new()),
+                response_headers: vec![("Skynet-Skylink".to_owned(), DATA_LINK.to_owned())],
+                sent: true,
+                ..Default::default()
+            });
+        }
+
+        t.execute_with(|| {
+            let portals = [
+                CommonOptions::default(),
+                CommonOptions {
+                    portal_url: "https://skyportal.xyz",
+                    ..Default::default()
+                },
+            ];
+            let opts = QuorumPinOptions {
+                resolve_entry_links: true,
+                timeout: 1000,
+                ..Default::default()
+            };
+
+            let results = pin_skylink_quorum(ENTRY_LINK, &portals, Some(&opts)).unwrap();
+
+            for result in results {
+                assert_eq!(
+                    result.unwrap(),
+                    PinResult {
+                        skylink: format_skylink(&str_to_bytes(DATA_LINK)),
+                        entry_link: Some(format_skylink(&str_to_bytes(ENTRY_LINK))),
+                    }
+                );
+            }
+        })
+    }
 }
@@ -4,7 +4,7 @@ use crate::crypto::{hash_data_key, hash_registry_entry, Signature};
 use crate::encoding::{
     decode_hex_bytes_to_bytes, decode_hex_to_bytes, encode_bytes_to_hex_bytes, vec_to_signature,
 };
-use crate::request::{execute_get, CommonOptions, RequestError};
+use crate::request::{execute_get, read_body, CommonOptions, PortalError, RequestError};
 use crate::skylink::{decode_skylink, new_ed25519_public_key, new_skylink_v2};
 use crate::util::{
     concat_strs, de_string_to_bytes, format_skylink, make_url, ser_bytes_to_string, str_to_bytes,
@@ -81,7 +81,7 @@ pub enum SetEntryError {
     /// Timeout error.
     TimeoutError,
     /// Unexpected status.
-    UnexpectedStatus(u16),
+    UnexpectedStatus(PortalError),
     /// UTF8 error.
     Utf8Error(str::Utf8Error),
 }
@@ -278,9 +278,9 @@ pub fn get_entry(
 
     let url = get_entry_url(public_key, data_key, Some(opts))?;
 
-    let resp = match execute_get(str::from_utf8(&url)?, &opts.common) {
+    let mut resp = match execute_get(str::from_utf8(&url)?, &opts.common) {
         // If a 404 status was found, return a null entry.
-        Err(RequestError::UnexpectedStatus(404)) => {
+        Err(RequestError::UnexpectedStatus(PortalError { status: 404, .. })) => {
             return Ok(SignedRegistryEntry {
                 entry: None,
                 signature: None,
@@ -290,7 +290,7 @@ pub fn get_entry(
     }?;
 
     // Read the response body and collect it to a vector of bytes.
-    let resp_bytes = resp.body().collect::<Vec<u8>>();
+    let resp_bytes = read_body(&mut resp, &opts.common)?;
     // Convert the bytes to a str.
     let resp_str = str::from_utf8(&resp_bytes)?;
     // Parse the str as JSON and store it in GetEntryResponse.
@@ -413,7 +413,10 @@ pub fn set_entry(
         .map_err(|_| SetEntryError::TimeoutError)??;
 
     if response.code >= 400 {
-        return Err(SetEntryError::UnexpectedStatus(response.code));
+        return Err(SetEntryError::UnexpectedStatus(PortalError::from_response(
+            response,
+            str::from_utf8(&url)?,
+        )));
     }
 
     Ok(())
@@ -500,6 +503,7 @@ pub fn get_entry_link(
 mod tests {
     use super::*;
     use crate::encoding::{decode_hex_to_bytes, vec_to_signature};
+    use crate::request::tests::StatusOffchainExt;
     use crate::util::str_to_bytes;
 
     use sp_core::offchain::{testing, OffchainWorkerExt};
@@ -658,6 +662,50 @@ mod tests {
         })
     }
 
+    #[test]
+    fn should_return_portal_error_when_setting_data_link() {
+        const DATA_LINK: &str = "sia://AAA6Z7R0sjreLCr35fJKhMXuc8CE6mxRhkHQtmgtJGzqvw";
+        const SET_ENTRY_REQUEST_JSON: &str = "{\"publickey\":{\"algorithm\":\"ed25519\",\"key\":[101,139,144,13,245,94,152,60,232,95,63,159,178,160,136,213,104,171,81,78,123,189,165,28,251,251,22,234,148,83,120,217]},\"datakey\":\"7c96a0537ab2aaac9cfe0eca217732f4e10791625b4ab4c17e4d91c8078713b9\",\"revision\":12,\"data\":[0,0,58,103,180,116,178,58,222,44,42,247,229,242,74,132,197,238,115,192,132,234,108,81,134,65,208,182,104,45,36,108,234,191],\"signature\":[230,73,17,53,225,37,252,223,75,109,202,5,44,45,201,52,121,240,88,90,19,152,205,231,144,102,84,116,33,37,14,161,175,164,154,149,217,169,202,41,231,14,246,177,148,13,87,79,63,4,68,103,39,101,246,148,163,249,164,91,163,243,12,9]}";
+
+        let (offchain, state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        // The portal rejects the second request, which sets the entry.
+        t.register_extension(OffchainWorkerExt::new(StatusOffchainExt::new(
+            offchain,
+            &[(1, 400)],
+        )));
+
+        // Add expected requests.
+        state.write().expect_request(testing::PendingRequest {
+            method: "GET".into(),
+            uri: EXPECTED_URL.into(),
+            response: Some(ENTRY_DATA_RESPONSE_JSON.into()),
+            sent: true,
+            ..Default::default()
+        });
+        state.write().expect_request(testing::PendingRequest {
+            method: "POST".into(),
+            uri: "https://siasky.net/skynet/registry".into(),
+            body: SET_ENTRY_REQUEST_JSON.into(),
+            response: Some(br#"{"message":"revision number too low"}"#.to_vec()),
+            sent: true,
+            ..Default::default()
+        });
+
+        t.execute_with(|| {
+            let err = set_data_link(PRIVATE_KEY, DATA_KEY, DATA_LINK, None).unwrap_err();
+
+            assert!(matches!(
+                err,
+                SetEntryDataError::SetEntryError(SetEntryError::UnexpectedStatus(PortalError {
+                    status: 400,
+                    message: Some(message),
+                    path,
+                })) if message == b"revision number too low" && path == b"/skynet/registry"
+            ));
+        })
+    }
+
     #[test]
     fn should_update_entry_data() {
         const DATA: &[u8] = &[1, 2, 3];
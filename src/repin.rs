@@ -0,0 +1,471 @@
+//! Re-pin scheduler.
+//!
+//! The schedule keeps the skylinks to keep alive in offchain storage, along with the block at which
+//! each was last pinned, so that offchain workers can re-pin the most overdue skylinks on each run.
+//! Block numbers are given as `u64` and can be converted from a runtime's block number with
+//! `UniqueSaturatedInto`.
+//!
+//! The schedule is locked while it is updated, so that concurrent offchain workers neither lose
+//! each other's changes nor pin the same skylinks twice.
+
+use crate::pin::{pin_skylink, PinError, PinOptions, PinResult};
+use crate::skylink::{is_skylink_v2, parse_skylink};
+use crate::util::{
+    concat_bytes, de_string_to_bytes, ser_bytes_to_string, str_to_bytes, trim_prefix,
+    URI_SKYNET_PREFIX,
+};
+
+use serde::{Deserialize, Serialize};
+use sp_core::offchain::{Duration, StorageKind};
+use sp_io::offchain;
+use sp_runtime::offchain::storage_lock::{StorageLock, Time};
+use sp_std::{prelude::Vec, str};
+
+/// The suffix of the key of the lock held while the schedule is updated.
+const LOCK_KEY_SUFFIX: &[u8] = b"::lock";
+
+/// Re-pin error.
+#[derive(Debug)]
+pub enum RepinError {
+    /// The skylink is an entry link, and resolving entry links was not enabled in the pin options.
+    CannotPinEntryLink,
+    /// The skylink could not be parsed.
+    InvalidSkylink,
+    /// JSON error.
+    JsonError(serde_json::Error),
+    /// The schedule is locked by another offchain worker.
+    Locked,
+}
+
+impl From<serde_json::Error> for RepinError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::JsonError(err)
+    }
+}
+
+/// Re-pin options.
+#[derive(Debug)]
+pub struct RepinOptions<'a> {
+    /// The options used to pin each skylink.
+    pub pin: PinOptions<'a>,
+    /// The offchain storage key under which the schedule is stored.
+    pub key: &'a [u8],
+    /// The number of blocks after which a pinned skylink is due to be re-pinned.
+    pub interval: u64,
+    /// The maximum number of requests sent per run. Resolving an entry link takes a request of its
+    /// own, so the budget must be at least 2 for entry links to be re-pinned.
+    pub budget: u32,
+    /// The number of blocks to wait before retrying a failed pin. Doubles with every consecutive
+    /// failure.
+    pub base_backoff: u64,
+    /// The maximum number of blocks to wait before retrying a failed pin.
+    pub max_backoff: u64,
+    /// The number of milliseconds after which the lock on the schedule expires, should its holder
+    /// not release it. Should cover a whole run.
+    pub lock_timeout: u64,
+}
+
+impl Default for RepinOptions<'_> {
+    fn default() -> Self {
+        Self {
+            pin: Default::default(),
+            key: b"skynet-substrate::repin-schedule",
+            // About a day of 6 second blocks.
+            interval: 14_400,
+            budget: 8,
+            base_backoff: 10,
+            max_backoff: 14_400,
+            lock_timeout: 60_000,
+        }
+    }
+}
+
+/// A skylink in the re-pin schedule.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct RepinEntry {
+    /// The skylink, without the `sia://` prefix.
+    #[serde(
+        serialize_with = "ser_bytes_to_string",
+        deserialize_with = "de_string_to_bytes"
+    )]
+    pub skylink: Vec<u8>,
+    /// The block at which the skylink was last pinned, if ever.
+    pub last_pinned: Option<u64>,
+    /// The block from which the skylink is due to be pinned.
+    pub next_pin: u64,
+    /// The number of consecutive failed pins.
+    pub failures: u32,
+}
+
+/// The outcome of re-pinning a skylink, given with the skylink as scheduled, without the `sia://`
+/// prefix. The skylink in a successful `PinResult` has the prefix.
+pub type RepinOutcome = (Vec<u8>, Result<PinResult, PinError>);
+
+/// Adds the `skylink` to the re-pin schedule, due immediately. Does nothing if it is already
+/// scheduled. Skylinks that could never be pinned with the pin options are rejected, as they would
+/// fail on every run.
+pub fn schedule_repin(skylink: &str, opts: Option<&RepinOptions>) -> Result<(), RepinError> {
+    let default = Default::default();
+    let opts = opts.unwrap_or(&default);
+
+    if parse_skylink(skylink).is_none() {
+        return Err(RepinError::InvalidSkylink);
+    }
+    if is_skylink_v2(skylink) && !opts.pin.resolve_entry_links {
+        return Err(RepinError::CannotPinEntryLink);
+    }
+
+    let skylink = str_to_bytes(trim_prefix(skylink, URI_SKYNET_PREFIX));
+
+    let lock_key = lock_key(opts);
+    let mut lock = StorageLock::<Time>::with_deadline(&lock_key, lock_timeout(opts));
+    let _guard = lock.try_lock().map_err(|_| RepinError::Locked)?;
+
+    let mut schedule = get_repin_schedule(Some(opts))?;
+    if schedule.iter().any(|entry| entry.skylink == skylink) {
+        return Ok(());
+    }
+    schedule.push(RepinEntry {
+        skylink,
+        last_pinned: None,
+        next_pin: 0,
+        failures: 0,
+    });
+    set_repin_schedule(&schedule, opts)
+}
+
+/// Removes the `skylink` from the re-pin schedule. The skylink stays pinned until it is unpinned.
+pub fn unschedule_repin(skylink: &str, opts: Option<&RepinOptions>) -> Result<(), RepinError> {
+    let default = Default::default();
+    let opts = opts.unwrap_or(&default);
+
+    let skylink = trim_prefix(skylink, URI_SKYNET_PREFIX).as_bytes();
+
+    let lock_key = lock_key(opts);
+    let mut lock = StorageLock::<Time>::with_deadline(&lock_key, lock_timeout(opts));
+    let _guard = lock.try_lock().map_err(|_| RepinError::Locked)?;
+
+    let mut schedule = get_repin_schedule(Some(opts))?;
+    schedule.retain(|entry| entry.skylink != skylink);
+    set_repin_schedule(&schedule, opts)
+}
+
+/// Gets the re-pin schedule. The schedule is empty if none was stored yet.
+pub fn get_repin_schedule(opts: Option<&RepinOptions>) -> Result<Vec<RepinEntry>, RepinError> {
+    let default = Default::default();
+    let opts = opts.unwrap_or(&default);
+
+    match offchain::local_storage_get(StorageKind::PERSISTENT, opts.key) {
+        Some(bytes) => Ok(serde_json::from_slice(&bytes)?),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Re-pins the skylinks due at `block`, most overdue first, until the request budget is used up.
+/// Skylinks that need more requests than are left are passed over until the next run. Failed pins
+/// are retried with exponential backoff. Returns the outcome for each skylink pinned.
+pub fn run_repin(block: u64, opts: Option<&RepinOptions>) -> Result<Vec<RepinOutcome>, RepinError> {
+    let default = Default::default();
+    let opts = opts.unwrap_or(&default);
+
+    // The lock is held for the whole run, so that no other worker pins the same skylinks.
+    let lock_key = lock_key(opts);
+    let mut lock = StorageLock::<Time>::with_deadline(&lock_key, lock_timeout(opts));
+    let _guard = lock.try_lock().map_err(|_| RepinError::Locked)?;
+
+    let mut schedule = get_repin_schedule(Some(opts))?;
+
+    let mut due = (0..schedule.len())
+        .filter(|i| schedule[*i].next_pin <= block)
+        .collect::<Vec<_>>();
+    due.sort_by_key(|i| schedule[*i].next_pin);
+
+    let mut budget = opts.budget;
+    let mut outcomes = Vec::new();
+    for i in due {
+        let cost = request_cost(&schedule[i].skylink, &opts.pin);
+        if cost > budget {
+            continue;
+        }
+        budget -= cost;
+
+        let entry = &mut schedule[i];
+        let result = match str::from_utf8(&entry.skylink) {
+            Ok(skylink) => pin_skylink(skylink, Some(&opts.pin)),
+            Err(err) => Err(err.into()),
+        };
+
+        if result.is_ok() {
+            entry.last_pinned = Some(block);
+            entry.next_pin = block.saturating_add(opts.interval);
+            entry.failures = 0;
+        } else {
+            entry.failures = entry.failures.saturating_add(1);
+            let backoff = opts
+                .base_backoff
+                .saturating_mul(1 << (entry.failures - 1).min(63))
+                .min(opts.max_backoff);
+            entry.next_pin = block.saturating_add(backoff);
+        }
+
+        outcomes.push((entry.skylink.clone(), result));
+    }
+
+    set_repin_schedule(&schedule, opts)?;
+
+    Ok(outcomes)
+}
+
+/// Gets the number of requests sent to pin the `skylink`.
+fn request_cost(skylink: &[u8], opts: &PinOptions) -> u32 {
+    match str::from_utf8(skylink) {
+        // Entry links are resolved first, or fail without a request.
+        Ok(skylink) if is_skylink_v2(skylink) => {
+            if opts.resolve_entry_links {
+                2
+            } else {
+                0
+            }
+        }
+        Ok(_) => 1,
+        Err(_) => 0,
+    }
+}
+
+fn set_repin_schedule(schedule: &[RepinEntry], opts: &RepinOptions) -> Result<(), RepinError> {
+    let bytes = serde_json::to_vec(schedule)?;
+    offchain::local_storage_set(StorageKind::PERSISTENT, opts.key, &bytes);
+
+    Ok(())
+}
+
+fn lock_key(opts: &RepinOptions) -> Vec<u8> {
+    concat_bytes(&[opts.key, LOCK_KEY_SUFFIX])
+}
+
+fn lock_timeout(opts: &RepinOptions) -> Duration {
+    Duration::from_millis(opts.lock_timeout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::format_skylink;
+
+    use sp_core::offchain::{testing, OffchainDbExt, OffchainWorkerExt};
+    use sp_io::TestExternalities;
+
+    const DATA_LINK_1: &str = "MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ";
+    const DATA_LINK_2: &str = "AAA6Z7R0sjreLCr35fJKhMXuc8CE6mxRhkHQtmgtJGzqvw";
+    const ENTRY_LINK: &str = "AQAZ1R-KcL4NO_xIVf0q8B1ngPVd6ec-Pu54O0Cto387Nw";
+
+    #[test]
+    fn should_schedule_and_unschedule_repins() {
+        let (offchain, _state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainDbExt::new(offchain.clone()));
+        t.register_extension(OffchainWorkerExt::new(offchain));
+
+        t.execute_with(|| {
+            schedule_repin(&format!("{}{}", URI_SKYNET_PREFIX, DATA_LINK_1), None).unwrap();
+            schedule_repin(DATA_LINK_2, None).unwrap();
+            // Already scheduled.
+            schedule_repin(DATA_LINK_1, None).unwrap();
+
+            let schedule = get_repin_schedule(None).unwrap();
+            assert_eq!(
+                schedule
+                    .iter()
+                    .map(|entry| &entry.skylink[..])
+                    .collect::<Vec<_>>(),
+                vec![DATA_LINK_1.as_bytes(), DATA_LINK_2.as_bytes()]
+            );
+            assert_eq!(schedule[0].last_pinned, None);
+
+            unschedule_repin(DATA_LINK_1, None).unwrap();
+            assert_eq!(get_repin_schedule(None).unwrap().len(), 1);
+
+            // Skylinks that can't be pinned are not scheduled.
+            assert!(matches!(
+                schedule_repin("foo", None),
+                Err(RepinError::InvalidSkylink)
+            ));
+            assert!(matches!(
+                schedule_repin(ENTRY_LINK, None),
+                Err(RepinError::CannotPinEntryLink)
+            ));
+            assert_eq!(get_repin_schedule(None).unwrap().len(), 1);
+        })
+    }
+
+    #[test]
+    fn should_fail_on_locked_or_corrupted_schedule() {
+        let (offchain, _state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainDbExt::new(offchain.clone()));
+        t.register_extension(OffchainWorkerExt::new(offchain));
+
+        t.execute_with(|| {
+            let opts = RepinOptions::default();
+
+            // Another worker holds the lock.
+            let lock_key = lock_key(&opts);
+            let mut lock = StorageLock::<Time>::new(&lock_key);
+            let guard = lock.lock();
+            assert!(matches!(
+                schedule_repin(DATA_LINK_1, None),
+                Err(RepinError::Locked)
+            ));
+            assert!(matches!(run_repin(0, None), Err(RepinError::Locked)));
+            drop(guard);
+
+            // A corrupted schedule is not taken as an empty one, which would be stored over it.
+            offchain::local_storage_set(StorageKind::PERSISTENT, opts.key, b"[{");
+            assert!(matches!(
+                get_repin_schedule(None),
+                Err(RepinError::JsonError(_))
+            ));
+            assert!(matches!(
+                schedule_repin(DATA_LINK_1, None),
+                Err(RepinError::JsonError(_))
+            ));
+            assert_eq!(
+                offchain::local_storage_get(StorageKind::PERSISTENT, opts.key),
+                Some(b"[{".to_vec())
+            );
+        })
+    }
+
+    #[test]
+    fn should_count_entry_link_resolution_against_budget() {
+        let (offchain, state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainDbExt::new(offchain.clone()));
+        t.register_extension(OffchainWorkerExt::new(offchain));
+
+        // Add expected requests. Only the entry link is re-pinned in the first run.
+        state.write().expect_request(testing::PendingRequest {
+            method: "GET".into(),
+            uri: "https://siasky.net/skynet/resolve/AQAZ1R-KcL4NO_xIVf0q8B1ngPVd6ec-Pu54O0Cto387Nw"
+                .into(),
+            response: Some(format!("{{\"skylink\":\"{}\"}}", DATA_LINK_2).into_bytes()),
+            sent: true,
+            ..Default::default()
+        });
+        for data_link in [DATA_LINK_2, DATA_LINK_1] {
+            state.write().expect_request(testing::PendingRequest {
+                method: "POST".into(),
+                uri: format!("https://siasky.net/skynet/pin/{}", data_link),
+                response: Some(Vec::new()),
+                response_headers: vec![("Skynet-Skylink".to_owned(), data_link.to_owned())],
+                sent: true,
+                ..Default::default()
+            });
+        }
+
+        t.execute_with(|| {
+            let opts = RepinOptions {
+                pin: PinOptions {
+                    resolve_entry_links: true,
+                    ..Default::default()
+                },
+                budget: 2,
+                ..Default::default()
+            };
+            schedule_repin(ENTRY_LINK, Some(&opts)).unwrap();
+            schedule_repin(DATA_LINK_1, Some(&opts)).unwrap();
+
+            let outcomes = run_repin(0, Some(&opts)).unwrap();
+            assert_eq!(outcomes.len(), 1);
+            assert_eq!(outcomes[0].0, str_to_bytes(ENTRY_LINK));
+            assert!(outcomes[0].1.is_ok());
+
+            let outcomes = run_repin(1, Some(&opts)).unwrap();
+            assert_eq!(outcomes.len(), 1);
+            assert_eq!(outcomes[0].0, str_to_bytes(DATA_LINK_1));
+        })
+    }
+
+    #[test]
+    fn should_repin_most_overdue_skylinks_within_budget() {
+        let (offchain, state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainDbExt::new(offchain.clone()));
+        t.register_extension(OffchainWorkerExt::new(offchain));
+
+        // Add expected requests. The first pin fails as the portal doesn't return the skylink.
+        state.write().expect_request(testing::PendingRequest {
+            method: "POST".into(),
+            uri: "https://siasky.net/skynet/pin/MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ"
+                .into(),
+            response: Some(Vec::new()),
+            sent: true,
+            ..Default::default()
+        });
+        state.write().expect_request(testing::PendingRequest {
+            method: "POST".into(),
+            uri: "https://siasky.net/skynet/pin/AAA6Z7R0sjreLCr35fJKhMXuc8CE6mxRhkHQtmgtJGzqvw"
+                .into(),
+            response: Some(Vec::new()),
+            response_headers: vec![("Skynet-Skylink".to_owned(), DATA_LINK_2.to_owned())],
+            sent: true,
+            ..Default::default()
+        });
+        state.write().expect_request(testing::PendingRequest {
+            method: "POST".into(),
+            uri: "https://siasky.net/skynet/pin/MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ"
+                .into(),
+            response: Some(Vec::new()),
+            response_headers: vec![("Skynet-Skylink".to_owned(), DATA_LINK_1.to_owned())],
+            sent: true,
+            ..Default::default()
+        });
+
+        t.execute_with(|| {
+            let opts = RepinOptions {
+                interval: 100,
+                budget: 1,
+                ..Default::default()
+            };
+            schedule_repin(DATA_LINK_1, Some(&opts)).unwrap();
+            schedule_repin(DATA_LINK_2, Some(&opts)).unwrap();
+
+            // Only one request fits in the budget, and it fails.
+            let outcomes = run_repin(5, Some(&opts)).unwrap();
+            assert_eq!(outcomes.len(), 1);
+            assert_eq!(outcomes[0].0, str_to_bytes(DATA_LINK_1));
+            assert!(outcomes[0].1.is_err());
+            let schedule = get_repin_schedule(Some(&opts)).unwrap();
+            assert_eq!(schedule[0].failures, 1);
+            assert_eq!(schedule[0].next_pin, 15);
+
+            // The second skylink is now the most overdue.
+            let outcomes = run_repin(15, Some(&opts)).unwrap();
+            assert_eq!(outcomes[0].0, str_to_bytes(DATA_LINK_2));
+            assert_eq!(
+                outcomes[0].1.as_ref().unwrap().skylink,
+                format_skylink(&str_to_bytes(DATA_LINK_2))
+            );
+
+            // The first skylink is retried after its backoff.
+            let outcomes = run_repin(16, Some(&opts)).unwrap();
+            assert_eq!(outcomes[0].0, str_to_bytes(DATA_LINK_1));
+            assert!(outcomes[0].1.is_ok());
+
+            let schedule = get_repin_schedule(Some(&opts)).unwrap();
+            assert_eq!(
+                schedule[0],
+                RepinEntry {
+                    skylink: str_to_bytes(DATA_LINK_1),
+                    last_pinned: Some(16),
+                    next_pin: 116,
+                    failures: 0,
+                }
+            );
+            assert_eq!(schedule[1].next_pin, 115);
+
+            // Nothing is due until the interval passes.
+            assert!(run_repin(100, Some(&opts)).unwrap().is_empty());
+        })
+    }
+}
@@ -1,11 +1,23 @@
+use crate::util::str_to_bytes;
+
 use sp_io::offchain;
 use sp_runtime::offchain::{self as rt_offchain, http};
+use sp_std::{prelude::Vec, str};
 
 /// The default Skynet portal URL.
 pub const DEFAULT_PORTAL_URL: &str = "https://siasky.net";
 
+/// The HEAD method, which `http::Method` has no variant for.
+pub const METHOD_HEAD: http::Method = http::Method::Other("HEAD");
+
+/// The maximum length of portal error messages kept in `PortalError`, in bytes.
+pub const MAX_PORTAL_ERROR_MESSAGE_LENGTH: usize = 256;
+
+/// The maximum number of bytes of an error response read to find the portal message.
+const MAX_ERROR_BODY_SIZE: usize = 4096;
+
 /// Options common to all methods.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct CommonOptions<'a> {
     /// The portal URL.
     pub portal_url: &'a str,
@@ -13,6 +25,9 @@ pub struct CommonOptions<'a> {
     pub custom_cookie: Option<&'a str>,
     /// Optional Skynet API key.
     pub skynet_api_key: Option<&'a str>,
+    /// Optional maximum size of response bodies, in bytes. Larger responses are rejected with
+    /// `RequestError::ResponseTooLarge` instead of being read into memory.
+    pub max_response_size: Option<u64>,
 }
 
 impl Default for CommonOptions<'_> {
@@ -21,6 +36,7 @@ impl Default for CommonOptions<'_> {
             portal_url: DEFAULT_PORTAL_URL,
             custom_cookie: None,
             skynet_api_key: None,
+            max_response_size: None,
         }
     }
 }
@@ -32,10 +48,40 @@ pub enum RequestError {
     HttpError(rt_offchain::HttpError),
     /// HTTP error.
     HttpError2(http::Error),
+    /// The response body was larger than the maximum response size, given here.
+    ResponseTooLarge(u64),
     /// Timeout error.
     TimeoutError,
     /// Unexpected status.
-    UnexpectedStatus(u16),
+    UnexpectedStatus(PortalError),
+}
+
+/// An error response from a portal.
+#[derive(Debug, PartialEq)]
+pub struct PortalError {
+    /// The status code.
+    pub status: u16,
+    /// The message returned by the portal, truncated to `MAX_PORTAL_ERROR_MESSAGE_LENGTH` bytes.
+    pub message: Option<Vec<u8>>,
+    /// The path of the request URL, without the query.
+    pub path: Vec<u8>,
+}
+
+impl PortalError {
+    /// Builds the error for an error `response` to a request to `url`.
+    pub fn from_response(response: http::Response, url: &str) -> Self {
+        // Error bodies are small, so don't let a portal make us read a large one.
+        let body = response
+            .body()
+            .take(MAX_ERROR_BODY_SIZE)
+            .collect::<Vec<_>>();
+
+        Self {
+            status: response.code,
+            message: parse_portal_message(&body),
+            path: str_to_bytes(get_url_path(url)),
+        }
+    }
 }
 
 impl From<http::Error> for RequestError {
@@ -50,10 +96,10 @@ impl From<rt_offchain::HttpError> for RequestError {
     }
 }
 
-pub fn add_headers<'a>(
-    mut request: http::Request<'a>,
+pub fn add_headers<'a, T: Default>(
+    mut request: http::Request<'a, T>,
     common: &CommonOptions,
-) -> http::Request<'a> {
+) -> http::Request<'a, T> {
     if let Some(cookie) = common.custom_cookie {
         request = request.add_header("Cookie", cookie);
     }
@@ -68,17 +114,49 @@ pub fn execute_get(
     url: &str,
     common_options: &CommonOptions,
 ) -> Result<http::Response, RequestError> {
-    // Initiate an external HTTP GET request. This is using high-level wrappers from `sp_runtime`.
-    let mut request = http::Request::get(url);
+    execute_method(http::Method::Get, url, common_options)
+}
+
+/// Executes a HEAD request. Only the status and headers of the response are transferred.
+pub fn execute_head(
+    url: &str,
+    common_options: &CommonOptions,
+) -> Result<http::Response, RequestError> {
+    execute_method(METHOD_HEAD, url, common_options)
+}
+
+/// Executes a request without a body using the given `method`.
+pub fn execute_method(
+    method: http::Method,
+    url: &str,
+    common_options: &CommonOptions,
+) -> Result<http::Response, RequestError> {
+    // Initiate an external HTTP request. This is using high-level wrappers from `sp_runtime`.
+    let mut request = http::Request::get(url).method(method);
 
     request = add_headers(request, common_options);
 
     execute_request(&request)
 }
 
-pub fn execute_request(request: &http::Request) -> Result<http::Response, RequestError> {
+pub fn execute_request<T, I>(request: &http::Request<T>) -> Result<http::Response, RequestError>
+where
+    T: Clone + Default + IntoIterator<Item = I>,
+    I: AsRef<[u8]>,
+{
     // Keeping the offchain worker execution time reasonable, so limiting the call to be within 3s.
-    let timeout = offchain::timestamp().add(rt_offchain::Duration::from_millis(3000));
+    execute_request_with_timeout(request, 3000)
+}
+
+pub fn execute_request_with_timeout<T, I>(
+    request: &http::Request<T>,
+    timeout_millis: u64,
+) -> Result<http::Response, RequestError>
+where
+    T: Clone + Default + IntoIterator<Item = I>,
+    I: AsRef<[u8]>,
+{
+    let timeout = offchain::timestamp().add(rt_offchain::Duration::from_millis(timeout_millis));
 
     let pending = request
         .clone()
@@ -95,8 +173,338 @@ pub fn execute_request(request: &http::Request) -> Result<http::Response, Reques
         .map_err(|_| RequestError::TimeoutError)??;
 
     if response.code >= 400 {
-        Err(RequestError::UnexpectedStatus(response.code))
+        Err(RequestError::UnexpectedStatus(PortalError::from_response(
+            response,
+            request.url,
+        )))
     } else {
         Ok(response)
     }
 }
+
+/// Reads the body of the `response`. Fails once the body exceeds the maximum response size in
+/// `common`, checking the `Content-Length` header up front when present.
+pub fn read_body(
+    response: &mut http::Response,
+    common: &CommonOptions,
+) -> Result<Vec<u8>, RequestError> {
+    read_body_with_limit(response, common.max_response_size)
+}
+
+/// Reads the body of the `response`, failing once it exceeds `max_size`.
+pub fn read_body_with_limit(
+    response: &mut http::Response,
+    max_size: Option<u64>,
+) -> Result<Vec<u8>, RequestError> {
+    let max_size = match max_size {
+        Some(max_size) => max_size,
+        None => return Ok(response.body().collect()),
+    };
+
+    let content_length = find_header(response.headers(), "Content-Length")
+        .and_then(|content_length| content_length.trim().parse::<u64>().ok());
+    if let Some(content_length) = content_length {
+        if content_length > max_size {
+            return Err(RequestError::ResponseTooLarge(max_size));
+        }
+    }
+
+    // The header may be missing or wrong, so keep counting while reading.
+    let mut body = Vec::with_capacity(content_length.unwrap_or(0) as usize);
+    for byte in response.body() {
+        if body.len() as u64 >= max_size {
+            return Err(RequestError::ResponseTooLarge(max_size));
+        }
+        body.push(byte);
+    }
+
+    Ok(body)
+}
+
+/// Finds the value of the header with the given `name`. Header names are matched
+/// case-insensitively.
+pub fn find_header<'a>(headers: &'a http::Headers, name: &str) -> Option<&'a str> {
+    headers
+        .raw
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name.as_bytes()))
+        .and_then(|(_, value)| str::from_utf8(value).ok())
+}
+
+/// Gets the portal message from an error response `body`. Portals answer with `{"message": ...}`,
+/// but proxies in front of them may answer with plain text.
+fn parse_portal_message(body: &[u8]) -> Option<Vec<u8>> {
+    // The message is parsed into an owned value, as strings with escapes can't be borrowed.
+    let json = serde_json::from_slice::<serde_json::Value>(body);
+    let message = match &json {
+        Ok(json) => json.get("message")?.as_str()?,
+        // The body may have been cut off in the middle of a character.
+        Err(_) => match str::from_utf8(body) {
+            Ok(text) => text,
+            Err(err) => str::from_utf8(&body[..err.valid_up_to()]).ok()?,
+        },
+    };
+
+    let message = message.trim();
+    if message.is_empty() {
+        return None;
+    }
+
+    let mut len = message.len().min(MAX_PORTAL_ERROR_MESSAGE_LENGTH);
+    while !message.is_char_boundary(len) {
+        len -= 1;
+    }
+
+    Some(str_to_bytes(&message[..len]))
+}
+
+/// Gets the path of the `url`, without the scheme, host and query.
+fn get_url_path(url: &str) -> &str {
+    let rest = match url.find("://") {
+        Some(i) => &url[i + 3..],
+        None => url,
+    };
+    let path = match rest.find('/') {
+        Some(i) => &rest[i..],
+        None => return "/",
+    };
+    match path.find(['?', '#']) {
+        Some(i) => &path[..i],
+        None => path,
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    use sp_core::offchain::{
+        self as core_offchain, testing, HttpError, HttpRequestId, HttpRequestStatus,
+        OffchainWorkerExt, OpaqueNetworkState, Timestamp,
+    };
+    use sp_core::OpaquePeerId;
+    use sp_io::TestExternalities;
+    use sp_std::collections::{btree_map::BTreeMap, btree_set::BTreeSet};
+
+    /// An offchain extension answering requests with the given statuses, keyed by the index of
+    /// the request in the order the requests were started. Other requests are answered with 200
+    /// like `testing::TestOffchainExt`, which it wraps. Body reads can be made to fail with
+    /// `with_failing_body`, and requests to miss their deadline with `with_deadline_reached`.
+    pub struct StatusOffchainExt {
+        inner: testing::TestOffchainExt,
+        statuses: Vec<(usize, u16)>,
+        // The index of the request started with each id. Ids are reused once a response has
+        // been read.
+        indices: BTreeMap<u16, usize>,
+        started: usize,
+        failing_bodies: Vec<usize>,
+        late: Vec<usize>,
+        // The ids of the failing bodies that have had their first chunk read.
+        read: BTreeSet<u16>,
+    }
+
+    impl StatusOffchainExt {
+        pub fn new(inner: testing::TestOffchainExt, statuses: &[(usize, u16)]) -> Self {
+            Self {
+                inner,
+                statuses: statuses.to_vec(),
+                indices: BTreeMap::new(),
+                started: 0,
+                failing_bodies: Vec::new(),
+                late: Vec::new(),
+                read: BTreeSet::new(),
+            }
+        }
+
+        /// Fails reading the body of the request at `index` after its first chunk, as if the
+        /// deadline was reached.
+        pub fn with_failing_body(mut self, index: usize) -> Self {
+            self.failing_bodies.push(index);
+            self
+        }
+
+        /// Answers the request at `index` as having missed its deadline.
+        pub fn with_deadline_reached(mut self, index: usize) -> Self {
+            self.late.push(index);
+            self
+        }
+    }
+
+    impl core_offchain::Externalities for StatusOffchainExt {
+        fn is_validator(&self) -> bool {
+            self.inner.is_validator()
+        }
+
+        fn network_state(&self) -> Result<OpaqueNetworkState, ()> {
+            self.inner.network_state()
+        }
+
+        fn timestamp(&mut self) -> Timestamp {
+            self.inner.timestamp()
+        }
+
+        fn sleep_until(&mut self, deadline: Timestamp) {
+            self.inner.sleep_until(deadline)
+        }
+
+        fn random_seed(&mut self) -> [u8; 32] {
+            self.inner.random_seed()
+        }
+
+        fn http_request_start(
+            &mut self,
+            method: &str,
+            uri: &str,
+            meta: &[u8],
+        ) -> Result<HttpRequestId, ()> {
+            let id = self.inner.http_request_start(method, uri, meta)?;
+            self.indices.insert(id.0, self.started);
+            self.started += 1;
+            Ok(id)
+        }
+
+        fn http_request_add_header(
+            &mut self,
+            request_id: HttpRequestId,
+            name: &str,
+            value: &str,
+        ) -> Result<(), ()> {
+            self.inner.http_request_add_header(request_id, name, value)
+        }
+
+        fn http_request_write_body(
+            &mut self,
+            request_id: HttpRequestId,
+            chunk: &[u8],
+            deadline: Option<Timestamp>,
+        ) -> Result<(), HttpError> {
+            self.inner
+                .http_request_write_body(request_id, chunk, deadline)
+        }
+
+        fn http_response_wait(
+            &mut self,
+            ids: &[HttpRequestId],
+            deadline: Option<Timestamp>,
+        ) -> Vec<HttpRequestStatus> {
+            let statuses = self.inner.http_response_wait(ids, deadline);
+
+            ids.iter()
+                .zip(statuses)
+                .map(|(id, status)| {
+                    let index = self.indices.get(&id.0);
+                    if matches!(index, Some(index) if self.late.contains(index)) {
+                        return HttpRequestStatus::DeadlineReached;
+                    }
+                    match self.statuses.iter().find(|(i, _)| Some(i) == index) {
+                        Some((_, code)) if status == HttpRequestStatus::Finished(200) => {
+                            HttpRequestStatus::Finished(*code)
+                        }
+                        _ => status,
+                    }
+                })
+                .collect()
+        }
+
+        fn http_response_headers(&mut self, request_id: HttpRequestId) -> Vec<(Vec<u8>, Vec<u8>)> {
+            self.inner.http_response_headers(request_id)
+        }
+
+        fn http_response_read_body(
+            &mut self,
+            request_id: HttpRequestId,
+            buffer: &mut [u8],
+            deadline: Option<Timestamp>,
+        ) -> Result<usize, HttpError> {
+            let failing = matches!(
+                self.indices.get(&request_id.0),
+                Some(index) if self.failing_bodies.contains(index)
+            );
+            if failing && !self.read.insert(request_id.0) {
+                return Err(HttpError::DeadlineReached);
+            }
+
+            self.inner
+                .http_response_read_body(request_id, buffer, deadline)
+        }
+
+        fn set_authorized_nodes(&mut self, nodes: Vec<OpaquePeerId>, authorized_only: bool) {
+            self.inner.set_authorized_nodes(nodes, authorized_only)
+        }
+    }
+
+    #[test]
+    fn should_return_portal_error_for_error_status() {
+        let (offchain, state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainWorkerExt::new(StatusOffchainExt::new(
+            offchain,
+            &[(0, 402)],
+        )));
+
+        // Add expected request.
+        state.write().expect_request(testing::PendingRequest {
+            method: "GET".into(),
+            uri: "https://siasky.net/skynet/skylink/foo?format=zip".into(),
+            response: Some(br#"{"message":"payment required"}"#.to_vec()),
+            sent: true,
+            ..Default::default()
+        });
+
+        t.execute_with(|| {
+            let err = execute_get(
+                "https://siasky.net/skynet/skylink/foo?format=zip",
+                &Default::default(),
+            )
+            .unwrap_err();
+
+            assert!(matches!(
+                err,
+                RequestError::UnexpectedStatus(PortalError {
+                    status: 402,
+                    message: Some(message),
+                    path,
+                }) if message == b"payment required" && path == b"/skynet/skylink/foo"
+            ));
+        })
+    }
+
+    #[test]
+    fn should_parse_portal_messages() {
+        assert_eq!(
+            parse_portal_message(b"{\"message\":\"revision number too low\"}"),
+            Some(str_to_bytes("revision number too low"))
+        );
+        assert_eq!(
+            parse_portal_message(b"502 Bad Gateway\n"),
+            Some(str_to_bytes("502 Bad Gateway"))
+        );
+        assert_eq!(parse_portal_message(b""), None);
+        assert_eq!(parse_portal_message(b"{\"error\":\"foo\"}"), None);
+
+        // Escaped messages are unescaped.
+        assert_eq!(
+            parse_portal_message(b"{\"message\":\"invalid \\\"skylink\\\" \\u0026 path\"}"),
+            Some(str_to_bytes("invalid \"skylink\" & path"))
+        );
+
+        // Long messages are truncated at a character boundary.
+        let long_message = format!("{{\"message\":\"a{}\"}}", "ü".repeat(200));
+        let message = parse_portal_message(long_message.as_bytes()).unwrap();
+        assert_eq!(message.len(), MAX_PORTAL_ERROR_MESSAGE_LENGTH - 1);
+        assert!(str::from_utf8(&message).is_ok());
+    }
+
+    #[test]
+    fn should_get_url_paths() {
+        assert_eq!(
+            get_url_path("https://siasky.net/skynet/registry?publickey=ed25519"),
+            "/skynet/registry"
+        );
+        assert_eq!(
+            get_url_path("https://siasky.net/skynet/skyfile"),
+            "/skynet/skyfile"
+        );
+        assert_eq!(get_url_path("https://siasky.net"), "/");
+    }
+}
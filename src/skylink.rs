@@ -96,6 +96,32 @@ impl SiaPublicKey {
     }
 }
 
+/// Parses the base64-encoded `skylink`, with or without the `sia://` prefix. Returns `None` if it is
+/// not a valid skylink.
+pub fn parse_skylink(skylink: &str) -> Option<SiaSkylink> {
+    let encoded = trim_prefix(skylink, URI_SKYNET_PREFIX);
+    if encoded.len() != BASE64_ENCODED_SKYLINK_SIZE {
+        return None;
+    }
+
+    let mut buf = [0; RAW_SKYLINK_SIZE];
+    let len = base64::decode_config_slice(encoded, base64::URL_SAFE_NO_PAD, &mut buf).ok()?;
+    if len != RAW_SKYLINK_SIZE {
+        return None;
+    }
+
+    Some(SiaSkylink {
+        bitfield: u16::from_le_bytes([buf[0], buf[1]]),
+        merkle_root: buf[2..].to_vec(),
+    })
+}
+
+/// Returns whether the `skylink` is a valid v2 skylink, i.e. an entry link. The version is stored
+/// in the two lowest bits of the bitfield, minus one.
+pub fn is_skylink_v2(skylink: &str) -> bool {
+    matches!(parse_skylink(skylink), Some(skylink) if skylink.bitfield & 3 == 1)
+}
+
 pub fn decode_skylink(skylink: &str) -> Vec<u8> {
     let encoded = trim_prefix(skylink, URI_SKYNET_PREFIX);
 
@@ -105,6 +131,7 @@ pub fn decode_skylink(skylink: &str) -> Vec<u8> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::encoding::encode_bytes_to_hex_bytes;
 
     #[test]
     fn test_decode_skylink() {
@@ -121,6 +148,26 @@ mod tests {
         );
     }
 
+    #[test]
+    fn should_parse_skylinks() {
+        const SKYLINK: &str = "sia://MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ";
+
+        let skylink = parse_skylink(SKYLINK).unwrap();
+        assert_eq!(skylink.bitfield, 48);
+        assert_eq!(
+            encode_bytes_to_hex_bytes(&skylink.merkle_root),
+            str_to_bytes("5d59692ee98113336ce8a060a3422dba46ce1ae876fbe2adba8bebcc31cc6299")
+        );
+        assert_eq!(
+            skylink.to_string(),
+            str_to_bytes(trim_prefix(SKYLINK, URI_SKYNET_PREFIX))
+        );
+
+        assert!(parse_skylink("").is_none());
+        assert!(parse_skylink("MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcxim").is_none());
+        assert!(parse_skylink("MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcxim$").is_none());
+    }
+
     #[test]
     fn should_return_correct_specifier() {
         const SPECIFIER: &str = "testing";
@@ -0,0 +1,280 @@
+//! Tar archive reader.
+//!
+//! Supports the ustar format along with PAX and GNU long names, which covers the archives returned
+//! by portals for `?format=tar` downloads.
+
+use crate::util::concat_bytes;
+
+use sp_std::{ops::Range, prelude::Vec, str};
+
+/// The size of tar headers and data blocks.
+const BLOCK_SIZE: usize = 512;
+
+/// Regular file types. Old archives use NUL instead of `0`.
+const TYPE_REGULAR: &[u8] = b"0\0";
+/// GNU long name for the next entry.
+const TYPE_GNU_LONG_NAME: u8 = b'L';
+/// PAX extended header for the next entry.
+const TYPE_PAX_HEADER: u8 = b'x';
+
+/// A file read from a tar archive, given as its path and contents.
+pub type TarFile<'a> = (Vec<u8>, &'a [u8]);
+
+/// A file in a tar archive, given as its path and the range of its contents in the archive.
+pub type TarEntry = (Vec<u8>, Range<usize>);
+
+/// Tar error.
+#[derive(Debug, PartialEq)]
+pub enum TarError {
+    /// A header checksum did not match.
+    InvalidChecksum,
+    /// A header field could not be parsed.
+    InvalidHeader,
+    /// The archive ended in the middle of an entry.
+    UnexpectedEof,
+}
+
+/// Reads the regular files in the tar `archive`. Returns the path and contents of each file, in
+/// archive order. Directories, links and other entries are skipped.
+pub fn read_tar(archive: &[u8]) -> Result<Vec<TarFile<'_>>, TarError> {
+    Ok(read_tar_entries(archive)?
+        .into_iter()
+        .map(|(path, range)| (path, &archive[range]))
+        .collect())
+}
+
+/// Reads the regular files in the tar `archive` like `read_tar`, but returns the range of each
+/// file's contents instead of borrowing them, so the archive can be kept along with the entries.
+pub fn read_tar_entries(archive: &[u8]) -> Result<Vec<TarEntry>, TarError> {
+    let mut files = Vec::new();
+    // A long name given by a previous GNU or PAX entry.
+    let mut long_name: Option<Vec<u8>> = None;
+
+    let mut pos = 0;
+    while pos < archive.len() {
+        let header = archive
+            .get(pos..pos + BLOCK_SIZE)
+            .ok_or(TarError::UnexpectedEof)?;
+        // The archive ends with zero blocks.
+        if header.iter().all(|b| *b == 0) {
+            break;
+        }
+        verify_checksum(header)?;
+
+        let size = usize::try_from(parse_number(&header[124..136])?)
+            .map_err(|_| TarError::InvalidHeader)?;
+        let data_start = pos + BLOCK_SIZE;
+        let data_range = data_start
+            .checked_add(size)
+            .filter(|data_end| *data_end <= archive.len())
+            .map(|data_end| data_start..data_end)
+            .ok_or(TarError::UnexpectedEof)?;
+        let data = &archive[data_range.clone()];
+        // Data is padded to whole blocks.
+        pos = data_start + size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+
+        match header[156] {
+            TYPE_GNU_LONG_NAME => long_name = Some(trim_nul(data).to_vec()),
+            TYPE_PAX_HEADER => {
+                if let Some(path) = parse_pax_path(data)? {
+                    long_name = Some(path.to_vec());
+                }
+            }
+            typeflag => {
+                let path = match long_name.take() {
+                    Some(path) => path,
+                    None => header_path(header),
+                };
+                if TYPE_REGULAR.contains(&typeflag) {
+                    files.push((path, data_range));
+                }
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+/// Gets the path from the name and ustar prefix fields of the `header`.
+fn header_path(header: &[u8]) -> Vec<u8> {
+    let name = trim_nul(&header[0..100]);
+    let prefix = if &header[257..262] == b"ustar" {
+        trim_nul(&header[345..500])
+    } else {
+        &[]
+    };
+
+    if prefix.is_empty() {
+        name.to_vec()
+    } else {
+        concat_bytes(&[prefix, b"/", name])
+    }
+}
+
+/// Verifies the checksum of the `header`, which is the sum of its bytes with the checksum field
+/// taken as spaces.
+fn verify_checksum(header: &[u8]) -> Result<(), TarError> {
+    let expected = parse_number(&header[148..156])?;
+
+    let actual = header
+        .iter()
+        .enumerate()
+        .map(|(i, b)| if (148..156).contains(&i) { b' ' } else { *b } as u64)
+        .sum::<u64>();
+
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(TarError::InvalidChecksum)
+    }
+}
+
+/// Parses a numeric header field. Numbers are octal, or base-256 when the high bit is set.
+fn parse_number(field: &[u8]) -> Result<u64, TarError> {
+    if field[0] & 0x80 != 0 {
+        return field[1..]
+            .iter()
+            .try_fold(u64::from(field[0] & 0x7f), |n, b| {
+                n.checked_mul(256)
+                    .map(|n| n + u64::from(*b))
+                    .ok_or(TarError::InvalidHeader)
+            });
+    }
+
+    let digits = str::from_utf8(field)
+        .map_err(|_| TarError::InvalidHeader)?
+        .trim_matches(|c| c == ' ' || c == '\0');
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(digits, 8).map_err(|_| TarError::InvalidHeader)
+}
+
+/// Finds the `path` record in PAX extended header `data`. Records have the form
+/// `<length> <key>=<value>\n`, where the length includes the whole record.
+fn parse_pax_path(mut data: &[u8]) -> Result<Option<&[u8]>, TarError> {
+    let mut path = None;
+
+    while !data.is_empty() {
+        let space = data
+            .iter()
+            .position(|b| *b == b' ')
+            .ok_or(TarError::InvalidHeader)?;
+        let len = str::from_utf8(&data[..space])
+            .ok()
+            .and_then(|len| len.parse::<usize>().ok())
+            .filter(|len| *len > space && *len <= data.len())
+            .ok_or(TarError::InvalidHeader)?;
+
+        let record = &data[space + 1..len];
+        let record = record.strip_suffix(b"\n").unwrap_or(record);
+        if let Some(value) = record.strip_prefix(b"path=") {
+            path = Some(value);
+        }
+
+        data = &data[len..];
+    }
+
+    Ok(path)
+}
+
+fn trim_nul(field: &[u8]) -> &[u8] {
+    let end = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+    &field[..end]
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Builds a tar entry with the given `name`, `typeflag` and `data`.
+    pub fn make_tar_entry(name: &str, prefix: &str, typeflag: u8, data: &[u8]) -> Vec<u8> {
+        let mut header = [0; BLOCK_SIZE];
+        header[0..name.len()].copy_from_slice(name.as_bytes());
+        header[100..107].copy_from_slice(b"0000644");
+        header[124..135].copy_from_slice(format!("{:011o}", data.len()).as_bytes());
+        header[156] = typeflag;
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+
+        header[148..156].copy_from_slice(b"        ");
+        let checksum = header.iter().map(|b| *b as u64).sum::<u64>();
+        header[148..155].copy_from_slice(format!("{:06o}\0", checksum).as_bytes());
+
+        let mut entry = header.to_vec();
+        entry.extend_from_slice(data);
+        entry.resize(BLOCK_SIZE + data.len().div_ceil(BLOCK_SIZE) * BLOCK_SIZE, 0);
+        entry
+    }
+
+    /// Builds a tar archive from the given entries, including the end-of-archive blocks.
+    pub fn make_tar(entries: &[Vec<u8>]) -> Vec<u8> {
+        let mut archive = entries.concat();
+        archive.resize(archive.len() + 2 * BLOCK_SIZE, 0);
+        archive
+    }
+
+    #[test]
+    fn should_read_tar() {
+        let long_path = format!("assets/{}.js", "a".repeat(120));
+        let pax_record = format!(" path=pax/{}\n", long_path);
+        // The length is three digits long and includes itself.
+        let pax_record = format!("{}{}", pax_record.len() + 3, pax_record);
+
+        let archive = make_tar(&[
+            make_tar_entry("index.html", "", b'0', b"<html></html>"),
+            make_tar_entry("assets", "", b'5', b""),
+            make_tar_entry("app.js", "assets", b'0', &[1; BLOCK_SIZE + 1]),
+            make_tar_entry(
+                "././@LongLink",
+                "",
+                b'L',
+                format!("{}\0", long_path).as_bytes(),
+            ),
+            make_tar_entry("truncated", "", b'0', b"gnu"),
+            make_tar_entry("PaxHeaders/truncated", "", b'x', pax_record.as_bytes()),
+            make_tar_entry("truncated", "", b'0', b"pax"),
+            make_tar_entry("empty", "", b'\0', b""),
+        ]);
+
+        let files = read_tar(&archive).unwrap();
+
+        assert_eq!(
+            files
+                .iter()
+                .map(|(path, data)| (str::from_utf8(path).unwrap().to_owned(), data.len()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("index.html".to_owned(), 13),
+                ("assets/app.js".to_owned(), BLOCK_SIZE + 1),
+                (long_path.clone(), 3),
+                (format!("pax/{}", long_path), 3),
+                ("empty".to_owned(), 0),
+            ]
+        );
+        assert_eq!(files[0].1, b"<html></html>");
+        assert_eq!(files[2].1, b"gnu");
+        assert_eq!(files[3].1, b"pax");
+    }
+
+    #[test]
+    fn should_fail_to_read_invalid_tar() {
+        let entry = make_tar_entry("file", "", b'0', b"foo");
+
+        // Bad checksum.
+        let mut corrupted = entry.clone();
+        corrupted[0] = b'g';
+        assert_eq!(read_tar(&corrupted), Err(TarError::InvalidChecksum));
+
+        // Truncated data and header.
+        assert_eq!(
+            read_tar(&entry[..BLOCK_SIZE + 1]),
+            Err(TarError::UnexpectedEof)
+        );
+        assert_eq!(read_tar(&entry[..100]), Err(TarError::UnexpectedEof));
+
+        // An empty archive has no files.
+        assert_eq!(read_tar(&make_tar(&[])), Ok(Vec::new()));
+    }
+}
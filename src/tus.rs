@@ -0,0 +1,473 @@
+//! Resumable large file uploads using the TUS protocol.
+
+use crate::encoding::{encode_base64, encode_bytes_to_hex_bytes};
+use crate::mime::get_mime_type;
+use crate::request::{
+    add_headers, execute_request_with_timeout, find_header, CommonOptions, PortalError, METHOD_HEAD,
+};
+use crate::upload::{upload_result_from_skylink, UploadError, UploadResult};
+use crate::util::{
+    concat_bytes, de_string_to_bytes, format_number, make_url, ser_bytes_to_string, str_to_bytes,
+};
+
+use serde::{Deserialize, Serialize};
+use sp_core::offchain::StorageKind;
+use sp_io::offchain;
+use sp_runtime::offchain::http;
+use sp_std::{cmp, prelude::Vec, str, vec};
+
+/// The TUS protocol version spoken by the portals.
+const TUS_VERSION: &str = "1.0.0";
+
+/// The default chunk size for large uploads, 4 MiB. Small enough for a chunk to be sent within the
+/// default request timeout, so that an offchain worker run can upload at least one chunk.
+pub const DEFAULT_TUS_CHUNK_SIZE: usize = 1 << 22;
+
+/// Large upload options.
+#[derive(Debug)]
+pub struct LargeUploadOptions<'a> {
+    /// Common options.
+    pub common: CommonOptions<'a>,
+    /// The endpoint to contact.
+    pub endpoint_large_upload: &'a str,
+    /// The number of bytes sent with each `PATCH` request.
+    pub chunk_size: usize,
+    /// Optional maximum number of chunks to upload in one call. The upload can be continued by
+    /// calling `upload_large_bytes` again, e.g. on the next offchain worker run.
+    pub max_chunks_per_run: Option<u32>,
+    /// Optional content type of the file. If not given, it is detected from the filename extension.
+    pub content_type: Option<&'a str>,
+    /// Timeout for each request.
+    pub timeout: u64,
+}
+
+impl Default for LargeUploadOptions<'_> {
+    fn default() -> Self {
+        Self {
+            common: Default::default(),
+            endpoint_large_upload: "/skynet/tus",
+            chunk_size: DEFAULT_TUS_CHUNK_SIZE,
+            max_chunks_per_run: None,
+            content_type: None,
+            timeout: 30_000,
+        }
+    }
+}
+
+/// Large upload status.
+#[derive(Debug, PartialEq)]
+pub enum LargeUploadStatus {
+    /// The upload is not complete yet.
+    InProgress {
+        /// The number of bytes uploaded so far.
+        offset: u64,
+        /// The total number of bytes to upload.
+        length: u64,
+    },
+    /// The upload is complete.
+    Complete(UploadResult),
+}
+
+/// The upload state persisted in offchain storage between runs.
+#[derive(Serialize, Deserialize)]
+struct LargeUploadState {
+    #[serde(
+        serialize_with = "ser_bytes_to_string",
+        deserialize_with = "de_string_to_bytes"
+    )]
+    url: Vec<u8>,
+    offset: u64,
+    length: u64,
+    /// The hex-encoded hash of the uploaded bytes.
+    #[serde(
+        serialize_with = "ser_bytes_to_string",
+        deserialize_with = "de_string_to_bytes"
+    )]
+    content_hash: Vec<u8>,
+    #[serde(
+        serialize_with = "ser_bytes_to_string",
+        deserialize_with = "de_string_to_bytes"
+    )]
+    filename: Vec<u8>,
+}
+
+/// Uploads `bytes` to a file with `filename` using the resumable TUS protocol.
+///
+/// The upload URL and offset are persisted in offchain storage under `storage_key`. If the upload
+/// does not complete in one call, it is resumed by calling this function again with the same
+/// `bytes` and `storage_key`, e.g. on a later offchain worker run. If the stored upload is of
+/// different bytes or a different filename, or has expired on the portal, a new upload is started.
+pub fn upload_large_bytes(
+    bytes: &[u8],
+    filename: &str,
+    storage_key: &[u8],
+    opts: Option<&LargeUploadOptions>,
+) -> Result<LargeUploadStatus, UploadError> {
+    let default = Default::default();
+    let opts = opts.unwrap_or(&default);
+
+    let length = bytes.len() as u64;
+    // The bytes are hashed on their own so that they don't have to be copied.
+    let content_hash = encode_bytes_to_hex_bytes(&sp_core_hashing::blake2_256(bytes));
+
+    // Resume the stored upload if it is of the same file, otherwise create a new upload.
+    let stored_state = get_upload_state(storage_key).filter(|state| {
+        state.length == length
+            && state.content_hash == content_hash
+            && state.filename == filename.as_bytes()
+    });
+    let resumed_state = match stored_state {
+        Some(state) => match get_upload_offset(str::from_utf8(&state.url)?, length, opts) {
+            Ok(offset) => Some(LargeUploadState { offset, ..state }),
+            // The portal has expired the upload.
+            Err(UploadError::UnexpectedStatus(PortalError {
+                status: 404 | 410, ..
+            })) => None,
+            Err(e) => return Err(e),
+        },
+        None => None,
+    };
+    let mut state = match resumed_state {
+        Some(state) => state,
+        None => create_upload(length, content_hash, filename, opts)?,
+    };
+    set_upload_state(storage_key, &state)?;
+
+    let chunk_size = cmp::max(opts.chunk_size, 1) as u64;
+    let mut chunks_uploaded = 0;
+    while state.offset < length {
+        if opts.max_chunks_per_run == Some(chunks_uploaded) {
+            return Ok(LargeUploadStatus::InProgress {
+                offset: state.offset,
+                length,
+            });
+        }
+
+        let end = cmp::min(state.offset + chunk_size, length);
+        let chunk = &bytes[state.offset as usize..end as usize];
+        state.offset = upload_chunk(str::from_utf8(&state.url)?, state.offset, chunk, opts)?;
+        set_upload_state(storage_key, &state)?;
+
+        chunks_uploaded += 1;
+    }
+
+    let skylink = get_upload_skylink(str::from_utf8(&state.url)?, opts)?;
+    clear_large_upload(storage_key);
+
+    Ok(LargeUploadStatus::Complete(upload_result_from_skylink(
+        &skylink,
+    )?))
+}
+
+/// Clears the upload state stored under `storage_key`, so that the next call to
+/// `upload_large_bytes` starts a new upload.
+pub fn clear_large_upload(storage_key: &[u8]) {
+    offchain::local_storage_clear(StorageKind::PERSISTENT, storage_key);
+}
+
+/// Creates a new upload of `length` bytes with the given content hash and returns its state.
+fn create_upload(
+    length: u64,
+    content_hash: Vec<u8>,
+    filename: &str,
+    opts: &LargeUploadOptions,
+) -> Result<LargeUploadState, UploadError> {
+    let url = make_url(&[opts.common.portal_url, opts.endpoint_large_upload]);
+
+    let filetype = opts.content_type.unwrap_or_else(|| get_mime_type(filename));
+    let metadata = concat_bytes(&[
+        &str_to_bytes("filename "),
+        &encode_base64(filename.as_bytes()),
+        &str_to_bytes(",filetype "),
+        &encode_base64(filetype.as_bytes()),
+    ]);
+    let length_str = format_number(length);
+
+    let mut request = http::Request::post(str::from_utf8(&url)?, Vec::<&[u8]>::new())
+        .add_header("Tus-Resumable", TUS_VERSION)
+        .add_header("Upload-Length", str::from_utf8(&length_str)?)
+        .add_header("Upload-Metadata", str::from_utf8(&metadata)?);
+    request = add_headers(request, &opts.common);
+
+    let mut response = execute_request_with_timeout(&request, opts.timeout)?;
+
+    let location = find_header(response.headers(), "Location")
+        .ok_or(UploadError::MissingHeader("Location"))?;
+    // The location may be relative to the portal.
+    let upload_url = if location.starts_with("http://") || location.starts_with("https://") {
+        str_to_bytes(location)
+    } else {
+        make_url(&[opts.common.portal_url, location])
+    };
+
+    Ok(LargeUploadState {
+        url: upload_url,
+        offset: 0,
+        length,
+        content_hash,
+        filename: str_to_bytes(filename),
+    })
+}
+
+/// Queries the portal for the current offset of the upload at `url`.
+fn get_upload_offset(
+    url: &str,
+    length: u64,
+    opts: &LargeUploadOptions,
+) -> Result<u64, UploadError> {
+    let mut request = http::Request::<Vec<&[u8]>>::new(url)
+        .method(METHOD_HEAD)
+        .add_header("Tus-Resumable", TUS_VERSION);
+    request = add_headers(request, &opts.common);
+
+    let mut response = execute_request_with_timeout(&request, opts.timeout)?;
+
+    parse_upload_offset(&mut response, length)
+}
+
+/// Uploads `chunk` at `offset` to the upload at `url`. Returns the new offset.
+fn upload_chunk(
+    url: &str,
+    offset: u64,
+    chunk: &[u8],
+    opts: &LargeUploadOptions,
+) -> Result<u64, UploadError> {
+    let offset_str = format_number(offset);
+
+    let mut request = http::Request::new(url)
+        .method(http::Method::Patch)
+        .body(vec![chunk])
+        .add_header("Tus-Resumable", TUS_VERSION)
+        .add_header("Upload-Offset", str::from_utf8(&offset_str)?)
+        .add_header("Content-Type", "application/offset+octet-stream");
+    request = add_headers(request, &opts.common);
+
+    let mut response = execute_request_with_timeout(&request, opts.timeout)?;
+
+    let new_offset = parse_upload_offset(&mut response, offset + chunk.len() as u64)?;
+    if new_offset <= offset {
+        return Err(UploadError::InvalidHeader("Upload-Offset"));
+    }
+    Ok(new_offset)
+}
+
+/// Gets the skylink of the completed upload at `url`.
+fn get_upload_skylink(url: &str, opts: &LargeUploadOptions) -> Result<Vec<u8>, UploadError> {
+    let mut request = http::Request::<Vec<&[u8]>>::new(url)
+        .method(METHOD_HEAD)
+        .add_header("Tus-Resumable", TUS_VERSION);
+    request = add_headers(request, &opts.common);
+
+    let mut response = execute_request_with_timeout(&request, opts.timeout)?;
+
+    let skylink = find_header(response.headers(), "Skynet-Skylink")
+        .ok_or(UploadError::MissingHeader("Skynet-Skylink"))?;
+    Ok(str_to_bytes(skylink))
+}
+
+/// Parses the `Upload-Offset` header of the `response`. The offset may not exceed `max_offset`.
+fn parse_upload_offset(response: &mut http::Response, max_offset: u64) -> Result<u64, UploadError> {
+    let offset = find_header(response.headers(), "Upload-Offset")
+        .ok_or(UploadError::MissingHeader("Upload-Offset"))?
+        .parse::<u64>()
+        .map_err(|_| UploadError::InvalidHeader("Upload-Offset"))?;
+
+    if offset > max_offset {
+        return Err(UploadError::InvalidHeader("Upload-Offset"));
+    }
+    Ok(offset)
+}
+
+fn get_upload_state(storage_key: &[u8]) -> Option<LargeUploadState> {
+    let bytes = offchain::local_storage_get(StorageKind::PERSISTENT, storage_key)?;
+
+    // Treat a corrupted state like a missing one and start over.
+    serde_json::from_slice(&bytes).ok()
+}
+
+fn set_upload_state(storage_key: &[u8], state: &LargeUploadState) -> Result<(), UploadError> {
+    let bytes = serde_json::to_vec(state)?;
+    offchain::local_storage_set(StorageKind::PERSISTENT, storage_key, &bytes);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::request::tests::StatusOffchainExt;
+
+    use sp_core::offchain::{testing, OffchainDbExt, OffchainWorkerExt};
+    use sp_io::TestExternalities;
+
+    const DATA: &str = "foobar";
+    const FILE_NAME: &str = "data.json";
+    const STORAGE_KEY: &[u8] = b"skynet::tus::data";
+    const UPLOAD_URL: &str = "https://siasky.net/skynet/tus/0123456789abcdef";
+    const DATA_LINK: &str = "MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ";
+    const EXPECTED_DATA_LINK: &str = "sia://MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ";
+    // "filename data.json,filetype application/json", base64-encoded.
+    const UPLOAD_METADATA: &str = "filename ZGF0YS5qc29u,filetype YXBwbGljYXRpb24vanNvbg==";
+
+    fn patch_request(offset: &str, body: &str, new_offset: &str) -> testing::PendingRequest {
+        testing::PendingRequest {
+            method: "PATCH".into(),
+            uri: UPLOAD_URL.into(),
+            body: body.into(),
+            headers: vec![
+                ("Tus-Resumable".to_owned(), TUS_VERSION.to_owned()),
+                ("Upload-Offset".to_owned(), offset.to_owned()),
+                (
+                    "Content-Type".to_owned(),
+                    "application/offset+octet-stream".to_owned(),
+                ),
+            ],
+            response: Some(vec![]),
+            response_headers: vec![("Upload-Offset".to_owned(), new_offset.to_owned())],
+            sent: true,
+            ..Default::default()
+        }
+    }
+
+    fn post_request() -> testing::PendingRequest {
+        testing::PendingRequest {
+            method: "POST".into(),
+            uri: "https://siasky.net/skynet/tus".into(),
+            headers: vec![
+                ("Tus-Resumable".to_owned(), TUS_VERSION.to_owned()),
+                ("Upload-Length".to_owned(), "6".to_owned()),
+                ("Upload-Metadata".to_owned(), UPLOAD_METADATA.to_owned()),
+            ],
+            response: Some(vec![]),
+            response_headers: vec![(
+                "Location".to_owned(),
+                "/skynet/tus/0123456789abcdef".to_owned(),
+            )],
+            sent: true,
+            ..Default::default()
+        }
+    }
+
+    fn head_request(response_headers: Vec<(String, String)>) -> testing::PendingRequest {
+        testing::PendingRequest {
+            method: "HEAD".into(),
+            uri: UPLOAD_URL.into(),
+            headers: vec![("Tus-Resumable".to_owned(), TUS_VERSION.to_owned())],
+            response: Some(vec![]),
+            response_headers,
+            sent: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn should_upload_large_file_across_runs() {
+        let (offchain, state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainWorkerExt::new(offchain.clone()));
+        t.register_extension(OffchainDbExt::new(offchain));
+
+        let opts = LargeUploadOptions {
+            chunk_size: 3,
+            max_chunks_per_run: Some(1),
+            ..Default::default()
+        };
+
+        // First run: create the upload and send the first chunk.
+        state.write().expect_request(post_request());
+        state.write().expect_request(patch_request("0", "foo", "3"));
+
+        t.execute_with(|| {
+            let status =
+                upload_large_bytes(DATA.as_bytes(), FILE_NAME, STORAGE_KEY, Some(&opts)).unwrap();
+
+            assert_eq!(
+                status,
+                LargeUploadStatus::InProgress {
+                    offset: 3,
+                    length: 6
+                }
+            );
+        });
+
+        // Second run: query the offset, send the last chunk and get the skylink.
+        state.write().expect_request(head_request(vec![(
+            "Upload-Offset".to_owned(),
+            "3".to_owned(),
+        )]));
+        state.write().expect_request(patch_request("3", "bar", "6"));
+        state.write().expect_request(head_request(vec![(
+            "skynet-skylink".to_owned(),
+            DATA_LINK.to_owned(),
+        )]));
+
+        t.execute_with(|| {
+            let status =
+                upload_large_bytes(DATA.as_bytes(), FILE_NAME, STORAGE_KEY, Some(&opts)).unwrap();
+
+            let result = match status {
+                LargeUploadStatus::Complete(result) => result,
+                _ => panic!("Expected the upload to be complete"),
+            };
+            assert_eq!(result.skylink, str_to_bytes(EXPECTED_DATA_LINK));
+
+            // The upload state should have been cleared.
+            assert_eq!(
+                offchain::local_storage_get(StorageKind::PERSISTENT, STORAGE_KEY),
+                None
+            );
+        });
+    }
+
+    #[test]
+    fn should_restart_changed_or_expired_upload() {
+        let (offchain, state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        // The fifth request, which queries the offset of the stored upload, finds it expired.
+        t.register_extension(OffchainWorkerExt::new(StatusOffchainExt::new(
+            offchain.clone(),
+            &[(4, 404)],
+        )));
+        t.register_extension(OffchainDbExt::new(offchain));
+
+        let opts = LargeUploadOptions {
+            chunk_size: 3,
+            max_chunks_per_run: Some(1),
+            ..Default::default()
+        };
+
+        // First run: create the upload and send the first chunk.
+        state.write().expect_request(post_request());
+        state.write().expect_request(patch_request("0", "foo", "3"));
+
+        t.execute_with(|| {
+            upload_large_bytes(DATA.as_bytes(), FILE_NAME, STORAGE_KEY, Some(&opts)).unwrap();
+        });
+
+        // Second run: different bytes of the same length start a new upload.
+        state.write().expect_request(post_request());
+        state.write().expect_request(patch_request("0", "baz", "3"));
+
+        t.execute_with(|| {
+            upload_large_bytes(b"bazqux", FILE_NAME, STORAGE_KEY, Some(&opts)).unwrap();
+        });
+
+        // Third run: the stored upload has expired, so a new upload is started.
+        state.write().expect_request(head_request(vec![]));
+        state.write().expect_request(post_request());
+        state.write().expect_request(patch_request("0", "baz", "3"));
+
+        t.execute_with(|| {
+            let status =
+                upload_large_bytes(b"bazqux", FILE_NAME, STORAGE_KEY, Some(&opts)).unwrap();
+
+            assert_eq!(
+                status,
+                LargeUploadStatus::InProgress {
+                    offset: 3,
+                    length: 6
+                }
+            );
+        });
+    }
+}
@@ -1,16 +1,29 @@
 //! Upload functions.
 
-use crate::request::CommonOptions;
+use crate::cache::{cache_skylink, get_cached_skylink, hash_upload, UploadCacheOptions};
+use crate::encoding::encode_bytes_to_hex_bytes;
+use crate::mime::get_mime_type;
+use crate::request::{add_headers, read_body, CommonOptions, PortalError, RequestError};
+use crate::skylink::parse_skylink;
 use crate::util::{
-    concat_bytes, concat_strs, de_string_to_bytes, format_skylink, make_url, str_to_bytes,
+    add_query_params, concat_bytes, concat_strs, de_string_to_bytes, format_skylink, make_url,
+    str_to_bytes, trim_prefix, URI_SKYNET_PREFIX,
 };
 
 use serde::Deserialize;
+use sp_core::offchain::StorageKind;
 use sp_io::offchain;
 use sp_runtime::offchain::{self as rt_offchain, http};
-use sp_std::{prelude::Vec, str, vec};
+use sp_std::{collections::btree_map::BTreeMap, prelude::Vec, str};
 
 const PORTAL_FILE_FIELD_NAME: &str = "file";
+const PORTAL_DIRECTORY_FILE_FIELD_NAME: &str = "files[]";
+
+/// The name of the form field, and of the skyfile metadata field, holding custom metadata.
+pub const CUSTOM_METADATA_FIELD_NAME: &str = "metadata";
+
+/// The suffix of the key under which the skylink of offchain indexed data is stored.
+const OFFCHAIN_INDEX_SKYLINK_KEY_SUFFIX: &[u8] = b"::skylink";
 
 /// Upload error.
 #[derive(Debug)]
@@ -19,12 +32,38 @@ pub enum UploadError {
     HttpError(rt_offchain::HttpError),
     /// HTTP error.
     HttpError2(http::Error),
+    /// Both a default path and `disable_default_path` were given.
+    ConflictingDefaultPathOptions,
+    /// The default path was not found among the uploaded files.
+    DefaultPathNotFound,
+    /// An empty path was given in the try files.
+    EmptyTryFile,
+    /// The skylink, merkle root and bitfield returned by the portal do not match.
+    InconsistentResponse,
+    /// The default path does not name a file at the root of the directory.
+    InvalidDefaultPath,
+    /// Error pages can only be given for 4xx and 5xx status codes.
+    InvalidErrorPageCode(u16),
+    /// A filename or path contained a quote or line break, which can't be sent in a multipart
+    /// request.
+    InvalidFilename,
+    /// A response header had an invalid value.
+    InvalidHeader(&'static str),
+    /// The portal returned an invalid skylink.
+    InvalidSkylink,
     /// JSON error.
     JsonError(serde_json::Error),
+    /// An expected response header was missing.
+    MissingHeader(&'static str),
+    /// No data was found in offchain storage under the given key.
+    OffchainIndexNotFound,
+    /// Request error. Timeouts and error statuses are reported as `TimeoutError` and
+    /// `UnexpectedStatus` instead.
+    RequestError(RequestError),
     /// Timeout error.
     TimeoutError,
     /// Unexpected status.
-    UnexpectedStatus(u16),
+    UnexpectedStatus(PortalError),
     /// UTF8 error.
     Utf8Error(str::Utf8Error),
 }
@@ -41,6 +80,17 @@ impl From<rt_offchain::HttpError> for UploadError {
     }
 }
 
+impl From<RequestError> for UploadError {
+    fn from(err: RequestError) -> Self {
+        // Report these the same way whether the request was built here or in `request`.
+        match err {
+            RequestError::TimeoutError => Self::TimeoutError,
+            RequestError::UnexpectedStatus(err) => Self::UnexpectedStatus(err),
+            err => Self::RequestError(err),
+        }
+    }
+}
+
 impl From<serde_json::Error> for UploadError {
     fn from(err: serde_json::Error) -> Self {
         Self::JsonError(err)
@@ -62,6 +112,26 @@ pub struct UploadOptions<'a> {
     pub endpoint_upload: &'a str,
     /// Timeout.
     pub timeout: u64,
+    /// Optional content type to send for the uploaded files. If not given, the content type of
+    /// each file is detected from its filename extension.
+    pub content_type: Option<&'a str>,
+    /// Optional files to try, in order, when a requested path is not found, e.g. `["index.html"]`
+    /// for a single-page app.
+    pub try_files: Option<&'a [&'a str]>,
+    /// Optional custom error pages, given as pairs of status code and path.
+    pub error_pages: Option<&'a [(u16, &'a str)]>,
+    /// Optional path to serve when the skylink is accessed without a path. Must be one of the
+    /// uploaded files.
+    pub default_path: Option<&'a str>,
+    /// Whether to serve the directory itself instead of a default path.
+    pub disable_default_path: bool,
+    /// Optional upload cache. If given, `upload_bytes` returns the cached skylink for contents
+    /// that were already uploaded with the same filename and options instead of uploading them
+    /// again.
+    pub cache: Option<&'a UploadCacheOptions<'a>>,
+    /// Optional custom metadata to embed in the skyfile metadata, e.g. to record the provenance of
+    /// the upload. Uploads with custom metadata bypass the upload cache.
+    pub metadata: Option<&'a serde_json::Value>,
 }
 
 impl Default for UploadOptions<'_> {
@@ -70,10 +140,38 @@ impl Default for UploadOptions<'_> {
             common: Default::default(),
             endpoint_upload: "/skynet/skyfile",
             timeout: 3_000,
+            content_type: None,
+            try_files: None,
+            error_pages: None,
+            default_path: None,
+            disable_default_path: false,
+            cache: None,
+            metadata: None,
         }
     }
 }
 
+/// Upload from offchain index options.
+#[derive(Debug, Default)]
+pub struct UploadFromOffchainIndexOptions<'a> {
+    /// Upload options.
+    pub upload_opts: Option<&'a UploadOptions<'a>>,
+    /// Whether to store the skylink in offchain storage, under the key returned by
+    /// `get_offchain_index_skylink_key`.
+    pub store_skylink: bool,
+}
+
+/// Upload result.
+#[derive(Debug, PartialEq)]
+pub struct UploadResult {
+    /// The skylink of the upload, with the `sia://` prefix.
+    pub skylink: Vec<u8>,
+    /// The merkle root of the upload.
+    pub merkle_root: Vec<u8>,
+    /// The bitfield of the skylink.
+    pub bitfield: u16,
+}
+
 // ref: https://serde.rs/container-attrs.html#crate
 #[derive(Deserialize, Default)]
 struct UploadResponse {
@@ -81,9 +179,7 @@ struct UploadResponse {
     #[serde(deserialize_with = "de_string_to_bytes")]
     skylink: Vec<u8>,
     #[serde(deserialize_with = "de_string_to_bytes")]
-    #[allow(dead_code)]
-    merkleroot: Vec<u8>,
-    #[allow(dead_code)]
+    merkleroot: Vec<u8>, // Hex string bytes
     bitfield: u16,
 }
 
@@ -92,13 +188,202 @@ pub fn upload_bytes(
     bytes: &[u8],
     filename: &str,
     opts: Option<&UploadOptions>,
-) -> Result<Vec<u8>, UploadError> {
+) -> Result<UploadResult, UploadError> {
+    let cache = match opts {
+        Some(UploadOptions {
+            cache: Some(cache),
+            metadata: None,
+            ..
+        }) => cache,
+        _ => return upload_chunks([bytes], filename, opts),
+    };
+
+    let hash = hash_upload(bytes, filename, opts.unwrap_or(&Default::default()));
+    if let Some(skylink) = get_cached_skylink(&hash, cache) {
+        return upload_result_from_skylink(&skylink);
+    }
+
+    let upload_result = upload_chunks([bytes], filename, opts)?;
+    cache_skylink(&hash, &upload_result.skylink, cache);
+
+    Ok(upload_result)
+}
+
+/// Upload the data given as `chunks` to a file with `filename`. The chunks are sent to the portal
+/// as they are, without first being copied into a single buffer.
+pub fn upload_chunks<'b, I>(
+    chunks: I,
+    filename: &str,
+    opts: Option<&UploadOptions>,
+) -> Result<UploadResult, UploadError>
+where
+    I: IntoIterator<Item = &'b [u8]>,
+{
     let default = &Default::default();
     let opts = opts.unwrap_or(default);
 
     // Construct the URL.
+    let url = make_upload_url(None, &[filename], opts)?;
+
+    let chunks = chunks.into_iter().collect::<Vec<_>>();
+
+    upload_multipart(
+        str::from_utf8(&url)?,
+        &[(PORTAL_FILE_FIELD_NAME, filename, &chunks)],
+        opts,
+    )
+}
+
+/// Upload the data written with `sp_io::offchain_index::set` under `key` to a file with
+/// `filename`. Indexed data can only be read from offchain workers.
+pub fn upload_from_offchain_index(
+    key: &[u8],
+    filename: &str,
+    opts: Option<&UploadFromOffchainIndexOptions>,
+) -> Result<UploadResult, UploadError> {
+    let default = Default::default();
+    let opts = opts.unwrap_or(&default);
+
+    // Data written through offchain indexing ends up in the persistent offchain storage.
+    let bytes = offchain::local_storage_get(StorageKind::PERSISTENT, key)
+        .ok_or(UploadError::OffchainIndexNotFound)?;
+
+    let upload_result = upload_bytes(&bytes, filename, opts.upload_opts)?;
+
+    if opts.store_skylink {
+        offchain::local_storage_set(
+            StorageKind::PERSISTENT,
+            &get_offchain_index_skylink_key(key),
+            &upload_result.skylink,
+        );
+    }
+
+    Ok(upload_result)
+}
+
+/// Gets the offchain storage key under which `upload_from_offchain_index` stores the skylink of the
+/// data at `key`.
+pub fn get_offchain_index_skylink_key(key: &[u8]) -> Vec<u8> {
+    concat_bytes(&[key, OFFCHAIN_INDEX_SKYLINK_KEY_SUFFIX])
+}
+
+/// Upload `files` to a directory with `dirname`. Each file is given as its path relative to the
+/// directory along with its contents. Returns the skylink of the directory.
+pub fn upload_directory(
+    files: &[(&str, &[u8])],
+    dirname: &str,
+    opts: Option<&UploadOptions>,
+) -> Result<UploadResult, UploadError> {
+    let default = &Default::default();
+    let opts = opts.unwrap_or(default);
+
+    // Construct the URL. The directory name is passed as the `filename` query parameter.
+    let paths = files.iter().map(|(path, _)| *path).collect::<Vec<_>>();
+    let url = make_upload_url(Some(dirname), &paths, opts)?;
+
+    let chunks = files.iter().map(|(_, bytes)| [*bytes]).collect::<Vec<_>>();
+    let parts = files
+        .iter()
+        .zip(&chunks)
+        .map(|((path, _), chunks)| (PORTAL_DIRECTORY_FILE_FIELD_NAME, *path, &chunks[..]))
+        .collect::<Vec<_>>();
+
+    upload_multipart(str::from_utf8(&url)?, &parts, opts)
+}
+
+/// Builds the upload URL, adding the `filename` and any web-app options as query parameters. The
+/// web-app options are validated against the `paths` of the files being uploaded.
+fn make_upload_url(
+    filename: Option<&str>,
+    paths: &[&str],
+    opts: &UploadOptions,
+) -> Result<Vec<u8>, UploadError> {
+    validate_web_app_options(paths, opts)?;
+
     let url = make_url(&[opts.common.portal_url, opts.endpoint_upload]);
 
+    let try_files = opts.try_files.map(serde_json::to_vec).transpose()?;
+    let error_pages = opts
+        .error_pages
+        .map(|error_pages| {
+            serde_json::to_vec(&error_pages.iter().copied().collect::<BTreeMap<_, _>>())
+        })
+        .transpose()?;
+
+    let mut params = Vec::<(&str, &str)>::new();
+    if let Some(filename) = filename {
+        params.push(("filename", filename));
+    }
+    if let Some(try_files) = &try_files {
+        params.push(("tryfiles", str::from_utf8(try_files)?));
+    }
+    if let Some(error_pages) = &error_pages {
+        params.push(("errorpages", str::from_utf8(error_pages)?));
+    }
+    if let Some(default_path) = opts.default_path {
+        params.push(("defaultpath", default_path));
+    }
+    if opts.disable_default_path {
+        params.push(("disabledefaultpath", "true"));
+    }
+
+    Ok(add_query_params(str::from_utf8(&url)?, &params))
+}
+
+/// Validates the web-app options against the `paths` of the files being uploaded. Matches the
+/// validation done by skyd.
+fn validate_web_app_options(paths: &[&str], opts: &UploadOptions) -> Result<(), UploadError> {
+    if let Some(default_path) = opts.default_path {
+        if opts.disable_default_path {
+            return Err(UploadError::ConflictingDefaultPathOptions);
+        }
+
+        // Only files at the root can be served by default.
+        let default_path = trim_prefix(default_path, "/");
+        if default_path.contains('/') {
+            return Err(UploadError::InvalidDefaultPath);
+        }
+        if !paths
+            .iter()
+            .any(|path| trim_prefix(path, "/") == default_path)
+        {
+            return Err(UploadError::DefaultPathNotFound);
+        }
+    }
+
+    if let Some(try_files) = opts.try_files {
+        if try_files.iter().any(|try_file| try_file.is_empty()) {
+            return Err(UploadError::EmptyTryFile);
+        }
+    }
+
+    if let Some(error_pages) = opts.error_pages {
+        if let Some((code, _)) = error_pages
+            .iter()
+            .find(|(code, _)| !(400..=599).contains(code))
+        {
+            return Err(UploadError::InvalidErrorPageCode(*code));
+        }
+    }
+
+    Ok(())
+}
+
+/// Uploads the given `parts` as a multipart request to `url`. Each part consists of the form field
+/// name, the filename and the file contents as a list of chunks.
+fn upload_multipart(
+    url: &str,
+    parts: &[(&str, &str, &[&[u8]])],
+    opts: &UploadOptions,
+) -> Result<UploadResult, UploadError> {
+    // Filenames are sent quoted in the part headers, where they can't be escaped.
+    if parts
+        .iter()
+        .any(|(_, filename, _)| filename.contains(['"', '\r', '\n']))
+    {
+        return Err(UploadError::InvalidFilename);
+    }
+
     // Build the request body boundary.
 
     let timestamp: u64 = offchain::timestamp().unix_millis();
@@ -116,37 +401,52 @@ pub fn upload_bytes(
     strs.push("----");
     let boundary = concat_strs(&strs);
 
-    // Build the request body.
+    // Build the multipart preamble of every part. Only these, the metadata and the epilogue are
+    // allocated, the file contents are sent as they are.
 
-    let mime = "application/octet-stream";
+    let mut preambles = Vec::with_capacity(parts.len());
+    for (field_name, filename, _) in parts {
+        let mime = opts.content_type.unwrap_or_else(|| get_mime_type(filename));
 
-    let disposition = concat_strs(&[
-        "form-data; name=\"",
-        PORTAL_FILE_FIELD_NAME,
-        "\"; filename=\"",
-        filename,
-        "\"",
-    ]);
-    let headers = concat_strs(&[
-        "Content-Disposition: ",
-        str::from_utf8(&disposition)?,
-        "\r\nContent-Type: ",
-        mime,
-        "\r\n",
-    ]);
+        let disposition = concat_strs(&[
+            "form-data; name=\"",
+            field_name,
+            "\"; filename=\"",
+            filename,
+            "\"",
+        ]);
+        preambles.push(make_part_preamble(
+            &boundary,
+            str::from_utf8(&disposition)?,
+            mime,
+        ));
+    }
+    let metadata = opts
+        .metadata
+        .map(|metadata| -> Result<_, UploadError> {
+            let disposition =
+                concat_strs(&["form-data; name=\"", CUSTOM_METADATA_FIELD_NAME, "\""]);
+            Ok((
+                make_part_preamble(&boundary, str::from_utf8(&disposition)?, "application/json"),
+                serde_json::to_vec(metadata)?,
+            ))
+        })
+        .transpose()?;
+    let epilogue = concat_bytes(&[&str_to_bytes("--"), &boundary, &str_to_bytes("--\r\n")]);
 
-    let body_bytes = concat_bytes(&[
-        &str_to_bytes("--"),
-        &boundary,
-        &str_to_bytes("\r\n"),
-        &headers,
-        &str_to_bytes("\r\n"),
-        bytes,
-        &str_to_bytes("\r\n"),
-        &str_to_bytes("--"),
-        &boundary,
-        &str_to_bytes("--\r\n"),
-    ]);
+    // Build the request body.
+
+    let mut body = Vec::<&[u8]>::new();
+    for ((_, _, chunks), preamble) in parts.iter().zip(&preambles) {
+        body.push(preamble);
+        // An empty chunk would finish the request early, so skip any.
+        body.extend(chunks.iter().filter(|chunk| !chunk.is_empty()));
+        body.push(b"\r\n");
+    }
+    if let Some((preamble, metadata)) = &metadata {
+        body.extend([&preamble[..], metadata, b"\r\n"]);
+    }
+    body.push(&epilogue);
 
     let content_type = concat_strs(&[
         "multipart/form-data; boundary=\"",
@@ -155,16 +455,9 @@ pub fn upload_bytes(
     ]);
 
     // Initiate an external HTTP POST request. This is using high-level wrappers from `sp_runtime`.
-    let mut request = rt_offchain::http::Request::post(str::from_utf8(&url)?, vec![body_bytes])
+    let mut request = rt_offchain::http::Request::post(url, body)
         .add_header("Content-Type", str::from_utf8(&content_type)?);
-
-    // NOTE: Can't use `add_headers` here because of a mismatched type error.
-    if let Some(cookie) = opts.common.custom_cookie {
-        request = request.add_header("Cookie", cookie);
-    }
-    if let Some(key) = opts.common.skynet_api_key {
-        request = request.add_header("Skynet-Api-Key", key);
-    }
+    request = add_headers(request, &opts.common);
 
     // Keeping the offchain worker execution time reasonable, so limiting the call to be within 3s.
     let timeout = offchain::timestamp().add(rt_offchain::Duration::from_millis(opts.timeout));
@@ -178,36 +471,78 @@ pub fn upload_bytes(
     // unwrapping it twice by two `?`
     //
     // ref: https://substrate.dev/rustdocs/v2.0.0-rc3/sp_runtime/offchain/http/struct.PendingRequest.html#method.try_wait
-    let response = pending
+    let mut response = pending
         .try_wait(timeout)
         .map_err(|_| UploadError::TimeoutError)??;
 
     if response.code >= 400 {
-        return Err(UploadError::UnexpectedStatus(response.code));
+        return Err(UploadError::UnexpectedStatus(PortalError::from_response(
+            response, url,
+        )));
     }
 
     // Read the response body and collect it to a vector of bytes.
-    let resp_bytes = response.body().collect::<Vec<u8>>();
+    let resp_bytes = read_body(&mut response, &opts.common)?;
     // Convert the bytes to a str.
     let resp_str = str::from_utf8(&resp_bytes)?;
     // Parse the str as JSON and store it in UploadResponse.
     let upload_response: UploadResponse = serde_json::from_str(resp_str)?;
-    Ok(format_skylink(&upload_response.skylink))
+
+    // Make sure the merkle root and bitfield match the skylink.
+    let upload_result = upload_result_from_skylink(&upload_response.skylink)?;
+    let merkle_root_hex = encode_bytes_to_hex_bytes(&upload_result.merkle_root);
+    if upload_result.bitfield != upload_response.bitfield
+        || !merkle_root_hex.eq_ignore_ascii_case(&upload_response.merkleroot)
+    {
+        return Err(UploadError::InconsistentResponse);
+    }
+
+    Ok(upload_result)
+}
+
+/// Builds the preamble of a multipart part with the given content disposition and type.
+fn make_part_preamble(boundary: &[u8], disposition: &str, content_type: &str) -> Vec<u8> {
+    concat_bytes(&[
+        &str_to_bytes("--"),
+        boundary,
+        &str_to_bytes("\r\nContent-Disposition: "),
+        &str_to_bytes(disposition),
+        &str_to_bytes("\r\nContent-Type: "),
+        &str_to_bytes(content_type),
+        &str_to_bytes("\r\n\r\n"),
+    ])
+}
+
+/// Builds the upload result from the given `skylink`, which may have the `sia://` prefix.
+pub fn upload_result_from_skylink(skylink: &[u8]) -> Result<UploadResult, UploadError> {
+    let skylink = trim_prefix(str::from_utf8(skylink)?, URI_SKYNET_PREFIX);
+    let sia_skylink = parse_skylink(skylink).ok_or(UploadError::InvalidSkylink)?;
+
+    Ok(UploadResult {
+        skylink: format_skylink(&str_to_bytes(skylink)),
+        merkle_root: sia_skylink.merkle_root,
+        bitfield: sia_skylink.bitfield,
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::encoding::decode_hex_to_bytes;
+    use crate::request::tests::StatusOffchainExt;
     use crate::util::str_to_bytes;
 
-    use sp_core::offchain::{testing, OffchainWorkerExt};
+    use sp_core::offchain::{testing, OffchainDbExt, OffchainWorkerExt};
     use sp_io::TestExternalities;
 
     const EXPECTED_DATA_LINK: &str = "sia://MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ";
+    const EXPECTED_MERKLE_ROOT: &str =
+        "5d59692ee98113336ce8a060a3422dba46ce1ae876fbe2adba8bebcc31cc6299";
+    const EXPECTED_BITFIELD: u16 = 48;
     const DATA: &str = "foo";
     const FILE_NAME: &str = "barfile";
     const REQUEST_BODY: &str = "--0000000000000000000000000000000000000000000000000000000000000000----\r\nContent-Disposition: form-data; name=\"file\"; filename=\"barfile\"\r\nContent-Type: application/octet-stream\r\n\r\nfoo\r\n--0000000000000000000000000000000000000000000000000000000000000000------\r\n";
-    const RESPONSE_JSON: &str = "{\"skylink\": \"MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ\", \"merkleroot\": \"foo\", \"bitfield\": 1028}";
+    const RESPONSE_JSON: &str = "{\"skylink\": \"MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ\", \"merkleroot\": \"5d59692ee98113336ce8a060a3422dba46ce1ae876fbe2adba8bebcc31cc6299\", \"bitfield\": 48}";
     const CONTENT_TYPE_MULTIPART: &str = "multipart/form-data; boundary=\"0000000000000000000000000000000000000000000000000000000000000000----\"";
 
     const JWT_COOKIE: &str = "MTYz...=="; // Don't use a full JWT as it's quite long.
@@ -233,10 +568,83 @@ mod tests {
 
         t.execute_with(|| {
             // Upload
-            let skylink_returned = upload_bytes(&str_to_bytes(DATA), FILE_NAME, None).unwrap();
+            let result_returned = upload_bytes(&str_to_bytes(DATA), FILE_NAME, None).unwrap();
 
             // Check the response.
-            assert_eq!(skylink_returned, str_to_bytes(EXPECTED_DATA_LINK));
+            assert_eq!(
+                result_returned,
+                UploadResult {
+                    skylink: str_to_bytes(EXPECTED_DATA_LINK),
+                    merkle_root: decode_hex_to_bytes(EXPECTED_MERKLE_ROOT),
+                    bitfield: EXPECTED_BITFIELD,
+                }
+            );
+        })
+    }
+
+    #[test]
+    fn should_return_portal_error_on_failed_upload() {
+        let (offchain, state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainWorkerExt::new(StatusOffchainExt::new(
+            offchain,
+            &[(0, 413)],
+        )));
+
+        // Add expected request.
+        state.write().expect_request(testing::PendingRequest {
+            method: "POST".into(),
+            uri: "https://siasky.net/skynet/skyfile".into(),
+            body: REQUEST_BODY.into(),
+            headers: vec![("Content-Type".to_owned(), CONTENT_TYPE_MULTIPART.to_owned())],
+            response: Some(br#"{"message":"upload too large"}"#.to_vec()),
+            sent: true,
+            ..Default::default()
+        });
+
+        t.execute_with(|| {
+            let err = upload_bytes(&str_to_bytes(DATA), FILE_NAME, None).unwrap_err();
+
+            assert!(matches!(
+                err,
+                UploadError::UnexpectedStatus(PortalError {
+                    status: 413,
+                    message: Some(message),
+                    path,
+                }) if message == b"upload too large" && path == b"/skynet/skyfile"
+            ));
+        })
+    }
+
+    #[test]
+    fn should_fail_on_inconsistent_upload_response() {
+        const INCONSISTENT_RESPONSE_JSONS: &[&str] = &[
+            "{\"skylink\": \"MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ\", \"merkleroot\": \"5d59692ee98113336ce8a060a3422dba46ce1ae876fbe2adba8bebcc31cc6299\", \"bitfield\": 1028}",
+            "{\"skylink\": \"MABdWWku6YETM2zooGCjQi26Rs4a6Hb74q26i-vMMcximQ\", \"merkleroot\": \"foo\", \"bitfield\": 48}",
+        ];
+
+        let (offchain, state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainWorkerExt::new(offchain));
+
+        // Add expected requests.
+        for response in INCONSISTENT_RESPONSE_JSONS {
+            state.write().expect_request(testing::PendingRequest {
+                method: "POST".into(),
+                uri: "https://siasky.net/skynet/skyfile".into(),
+                body: REQUEST_BODY.into(),
+                headers: vec![("Content-Type".to_owned(), CONTENT_TYPE_MULTIPART.to_owned())],
+                response: Some(str_to_bytes(response)),
+                sent: true,
+                ..Default::default()
+            });
+        }
+
+        t.execute_with(|| {
+            for _ in INCONSISTENT_RESPONSE_JSONS {
+                let result = upload_bytes(&str_to_bytes(DATA), FILE_NAME, None);
+                assert!(matches!(result, Err(UploadError::InconsistentResponse)));
+            }
         })
     }
 
@@ -275,7 +683,7 @@ mod tests {
             .unwrap();
 
             // Check the response.
-            assert_eq!(skylink_returned, str_to_bytes(EXPECTED_DATA_LINK));
+            assert_eq!(skylink_returned.skylink, str_to_bytes(EXPECTED_DATA_LINK));
         })
     }
 
@@ -317,7 +725,393 @@ mod tests {
             .unwrap();
 
             // Check the response.
-            assert_eq!(skylink_returned, str_to_bytes(EXPECTED_DATA_LINK));
+            assert_eq!(skylink_returned.skylink, str_to_bytes(EXPECTED_DATA_LINK));
+        })
+    }
+
+    #[test]
+    fn should_upload_directory_and_return_directory_link() {
+        const DIR_NAME: &str = "report bundle";
+        const DIRECTORY_REQUEST_BODY: &str = "--0000000000000000000000000000000000000000000000000000000000000000----\r\nContent-Disposition: form-data; name=\"files[]\"; filename=\"report.pdf\"\r\nContent-Type: application/pdf\r\n\r\nfoo\r\n--0000000000000000000000000000000000000000000000000000000000000000----\r\nContent-Disposition: form-data; name=\"files[]\"; filename=\"attachments/bar\"\r\nContent-Type: application/octet-stream\r\n\r\nbar\r\n--0000000000000000000000000000000000000000000000000000000000000000------\r\n";
+
+        let (offchain, state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainWorkerExt::new(offchain));
+
+        // Add expected request.
+        state.write().expect_request(testing::PendingRequest {
+            method: "POST".into(),
+            uri: "https://siasky.net/skynet/skyfile?filename=report%20bundle".into(),
+            body: DIRECTORY_REQUEST_BODY.into(),
+            headers: vec![("Content-Type".to_owned(), CONTENT_TYPE_MULTIPART.to_owned())],
+            response: Some(RESPONSE_JSON.into()),
+            sent: true,
+            ..Default::default()
+        });
+
+        t.execute_with(|| {
+            // Upload
+            let files: &[(&str, &[u8])] = &[("report.pdf", b"foo"), ("attachments/bar", b"bar")];
+            let skylink_returned = upload_directory(files, DIR_NAME, None).unwrap();
+
+            // Check the response.
+            assert_eq!(skylink_returned.skylink, str_to_bytes(EXPECTED_DATA_LINK));
+        })
+    }
+
+    #[test]
+    fn should_upload_with_web_app_options() {
+        const WEB_APP_REQUEST_BODY: &str = "--0000000000000000000000000000000000000000000000000000000000000000----\r\nContent-Disposition: form-data; name=\"files[]\"; filename=\"index.html\"\r\nContent-Type: text/html\r\n\r\nfoo\r\n--0000000000000000000000000000000000000000000000000000000000000000------\r\n";
+
+        let (offchain, state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainWorkerExt::new(offchain));
+
+        // Add expected request.
+        state.write().expect_request(testing::PendingRequest {
+            method: "POST".into(),
+            uri: "https://siasky.net/skynet/skyfile?filename=app&tryfiles=%5B%22index.html%22%5D&errorpages=%7B%22404%22%3A%22%2F404.html%22%7D&defaultpath=%2Findex.html".into(),
+            body: WEB_APP_REQUEST_BODY.into(),
+            headers: vec![("Content-Type".to_owned(), CONTENT_TYPE_MULTIPART.to_owned())],
+            response: Some(RESPONSE_JSON.into()),
+            sent: true,
+            ..Default::default()
+        });
+
+        t.execute_with(|| {
+            // Upload
+            let files: &[(&str, &[u8])] = &[("index.html", b"foo")];
+            let skylink_returned = upload_directory(
+                files,
+                "app",
+                Some(&UploadOptions {
+                    try_files: Some(&["index.html"]),
+                    error_pages: Some(&[(404, "/404.html")]),
+                    default_path: Some("/index.html"),
+                    ..Default::default()
+                }),
+            )
+            .unwrap();
+
+            // Check the response.
+            assert_eq!(skylink_returned.skylink, str_to_bytes(EXPECTED_DATA_LINK));
+        })
+    }
+
+    #[test]
+    fn should_reject_invalid_web_app_options() {
+        let files: &[(&str, &[u8])] = &[("index.html", b"foo")];
+
+        let result = upload_directory(
+            files,
+            "app",
+            Some(&UploadOptions {
+                default_path: Some("missing.html"),
+                ..Default::default()
+            }),
+        );
+        assert!(matches!(result, Err(UploadError::DefaultPathNotFound)));
+
+        let files_in_dirs: &[(&str, &[u8])] =
+            &[("index.html", b"foo"), ("docs/index.html", b"bar")];
+        let result = upload_directory(
+            files_in_dirs,
+            "app",
+            Some(&UploadOptions {
+                default_path: Some("/docs/index.html"),
+                ..Default::default()
+            }),
+        );
+        assert!(matches!(result, Err(UploadError::InvalidDefaultPath)));
+
+        let result = upload_directory(
+            files,
+            "app",
+            Some(&UploadOptions {
+                default_path: Some("index.html"),
+                disable_default_path: true,
+                ..Default::default()
+            }),
+        );
+        assert!(matches!(
+            result,
+            Err(UploadError::ConflictingDefaultPathOptions)
+        ));
+
+        let result = upload_directory(
+            files,
+            "app",
+            Some(&UploadOptions {
+                try_files: Some(&["index.html", ""]),
+                ..Default::default()
+            }),
+        );
+        assert!(matches!(result, Err(UploadError::EmptyTryFile)));
+
+        let result = upload_directory(
+            files,
+            "app",
+            Some(&UploadOptions {
+                error_pages: Some(&[(200, "/index.html")]),
+                ..Default::default()
+            }),
+        );
+        assert!(matches!(
+            result,
+            Err(UploadError::InvalidErrorPageCode(200))
+        ));
+    }
+
+    #[test]
+    fn should_reject_invalid_filenames() {
+        for path in [
+            "a\"b.txt",
+            "a\r\nContent-Type: text/html.txt",
+            "dir\n/a.txt",
+        ] {
+            let files: &[(&str, &[u8])] = &[(path, b"foo")];
+            let result = upload_directory(files, "app", None);
+            assert!(matches!(result, Err(UploadError::InvalidFilename)));
+        }
+    }
+
+    #[test]
+    fn should_upload_with_detected_and_custom_content_types() {
+        const JSON_REQUEST_BODY: &str = "--0000000000000000000000000000000000000000000000000000000000000000----\r\nContent-Disposition: form-data; name=\"file\"; filename=\"data.json\"\r\nContent-Type: application/json\r\n\r\nfoo\r\n--0000000000000000000000000000000000000000000000000000000000000000------\r\n";
+        const CUSTOM_REQUEST_BODY: &str = "--0000000000000000000000000000000000000000000000000000000000000000----\r\nContent-Disposition: form-data; name=\"file\"; filename=\"data.json\"\r\nContent-Type: text/plain\r\n\r\nfoo\r\n--0000000000000000000000000000000000000000000000000000000000000000------\r\n";
+
+        let (offchain, state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainWorkerExt::new(offchain));
+
+        // Add expected requests.
+        for body in [JSON_REQUEST_BODY, CUSTOM_REQUEST_BODY] {
+            state.write().expect_request(testing::PendingRequest {
+                method: "POST".into(),
+                uri: "https://siasky.net/skynet/skyfile".into(),
+                body: body.into(),
+                headers: vec![("Content-Type".to_owned(), CONTENT_TYPE_MULTIPART.to_owned())],
+                response: Some(RESPONSE_JSON.into()),
+                sent: true,
+                ..Default::default()
+            });
+        }
+
+        t.execute_with(|| {
+            // Upload with the content type detected from the filename.
+            let skylink_returned = upload_bytes(&str_to_bytes(DATA), "data.json", None).unwrap();
+            assert_eq!(skylink_returned.skylink, str_to_bytes(EXPECTED_DATA_LINK));
+
+            // Upload with a custom content type.
+            let skylink_returned = upload_bytes(
+                &str_to_bytes(DATA),
+                "data.json",
+                Some(&UploadOptions {
+                    content_type: Some("text/plain"),
+                    ..Default::default()
+                }),
+            )
+            .unwrap();
+            assert_eq!(skylink_returned.skylink, str_to_bytes(EXPECTED_DATA_LINK));
+        })
+    }
+
+    #[test]
+    fn should_upload_chunks() {
+        let (offchain, state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainWorkerExt::new(offchain));
+
+        // Add expected request.
+        state.write().expect_request(testing::PendingRequest {
+            method: "POST".into(),
+            uri: "https://siasky.net/skynet/skyfile".into(),
+            body: REQUEST_BODY.into(),
+            headers: vec![("Content-Type".to_owned(), CONTENT_TYPE_MULTIPART.to_owned())],
+            response: Some(RESPONSE_JSON.into()),
+            sent: true,
+            ..Default::default()
+        });
+
+        t.execute_with(|| {
+            // Upload
+            let chunks: [&[u8]; 3] = [b"f", b"", b"oo"];
+            let result_returned = upload_chunks(chunks, FILE_NAME, None).unwrap();
+
+            // Check the response.
+            assert_eq!(result_returned.skylink, str_to_bytes(EXPECTED_DATA_LINK));
+        })
+    }
+
+    #[test]
+    fn should_upload_with_custom_metadata() {
+        const REQUEST_BODY_WITH_METADATA: &str = "--0000000000000000000000000000000000000000000000000000000000000000----\r\nContent-Disposition: form-data; name=\"file\"; filename=\"barfile\"\r\nContent-Type: application/octet-stream\r\n\r\nfoo\r\n--0000000000000000000000000000000000000000000000000000000000000000----\r\nContent-Disposition: form-data; name=\"metadata\"\r\nContent-Type: application/json\r\n\r\n{\"block\":42,\"extrinsic\":\"0x01\"}\r\n--0000000000000000000000000000000000000000000000000000000000000000------\r\n";
+
+        let (offchain, state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainWorkerExt::new(offchain.clone()));
+        t.register_extension(OffchainDbExt::new(offchain));
+
+        // Add expected requests. Uploads with metadata should not be cached.
+        for _ in 0..2 {
+            state.write().expect_request(testing::PendingRequest {
+                method: "POST".into(),
+                uri: "https://siasky.net/skynet/skyfile".into(),
+                body: REQUEST_BODY_WITH_METADATA.into(),
+                headers: vec![("Content-Type".to_owned(), CONTENT_TYPE_MULTIPART.to_owned())],
+                response: Some(RESPONSE_JSON.into()),
+                sent: true,
+                ..Default::default()
+            });
+        }
+
+        t.execute_with(|| {
+            let cache = Default::default();
+            let metadata = serde_json::json!({ "block": 42, "extrinsic": "0x01" });
+            let opts = UploadOptions {
+                cache: Some(&cache),
+                metadata: Some(&metadata),
+                ..Default::default()
+            };
+
+            // Upload
+            for _ in 0..2 {
+                let result_returned =
+                    upload_bytes(DATA.as_bytes(), FILE_NAME, Some(&opts)).unwrap();
+
+                // Check the response.
+                assert_eq!(result_returned.skylink, str_to_bytes(EXPECTED_DATA_LINK));
+            }
+        })
+    }
+
+    #[test]
+    fn should_upload_from_offchain_index_and_store_skylink() {
+        const INDEX_KEY: &[u8] = b"pallet::blob::1";
+
+        let (offchain, state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainWorkerExt::new(offchain.clone()));
+        t.register_extension(OffchainDbExt::new(offchain));
+
+        // Add expected request.
+        state.write().expect_request(testing::PendingRequest {
+            method: "POST".into(),
+            uri: "https://siasky.net/skynet/skyfile".into(),
+            body: REQUEST_BODY.into(),
+            headers: vec![("Content-Type".to_owned(), CONTENT_TYPE_MULTIPART.to_owned())],
+            response: Some(RESPONSE_JSON.into()),
+            sent: true,
+            ..Default::default()
+        });
+
+        t.execute_with(|| {
+            // Nothing has been indexed yet.
+            let result = upload_from_offchain_index(INDEX_KEY, FILE_NAME, None);
+            assert!(matches!(result, Err(UploadError::OffchainIndexNotFound)));
+
+            // Simulate the data having been written with `offchain_index::set`.
+            offchain::local_storage_set(StorageKind::PERSISTENT, INDEX_KEY, DATA.as_bytes());
+
+            // Upload
+            let result_returned = upload_from_offchain_index(
+                INDEX_KEY,
+                FILE_NAME,
+                Some(&UploadFromOffchainIndexOptions {
+                    store_skylink: true,
+                    ..Default::default()
+                }),
+            )
+            .unwrap();
+
+            // Check the response and the stored skylink.
+            assert_eq!(result_returned.skylink, str_to_bytes(EXPECTED_DATA_LINK));
+            assert_eq!(
+                offchain::local_storage_get(
+                    StorageKind::PERSISTENT,
+                    &get_offchain_index_skylink_key(INDEX_KEY)
+                ),
+                Some(str_to_bytes(EXPECTED_DATA_LINK))
+            );
+        })
+    }
+
+    #[test]
+    fn should_skip_upload_of_cached_bytes() {
+        let (offchain, state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainWorkerExt::new(offchain.clone()));
+        t.register_extension(OffchainDbExt::new(offchain));
+
+        // Add a single expected request. The second upload should be served from the cache.
+        state.write().expect_request(testing::PendingRequest {
+            method: "POST".into(),
+            uri: "https://siasky.net/skynet/skyfile".into(),
+            body: REQUEST_BODY.into(),
+            headers: vec![("Content-Type".to_owned(), CONTENT_TYPE_MULTIPART.to_owned())],
+            response: Some(RESPONSE_JSON.into()),
+            sent: true,
+            ..Default::default()
+        });
+
+        t.execute_with(|| {
+            let cache = Default::default();
+            let opts = UploadOptions {
+                cache: Some(&cache),
+                ..Default::default()
+            };
+
+            let result_uploaded = upload_bytes(DATA.as_bytes(), FILE_NAME, Some(&opts)).unwrap();
+            let result_cached = upload_bytes(DATA.as_bytes(), FILE_NAME, Some(&opts)).unwrap();
+
+            assert_eq!(result_uploaded.skylink, str_to_bytes(EXPECTED_DATA_LINK));
+            assert_eq!(result_cached, result_uploaded);
+        })
+    }
+
+    #[test]
+    fn should_not_reuse_cached_skylink_for_other_content_type() {
+        const REQUEST_BODY_TEXT: &str = "--0000000000000000000000000000000000000000000000000000000000000000----\r\nContent-Disposition: form-data; name=\"file\"; filename=\"barfile\"\r\nContent-Type: text/plain\r\n\r\nfoo\r\n--0000000000000000000000000000000000000000000000000000000000000000------\r\n";
+
+        let (offchain, state) = testing::TestOffchainExt::new();
+        let mut t = TestExternalities::default();
+        t.register_extension(OffchainWorkerExt::new(offchain.clone()));
+        t.register_extension(OffchainDbExt::new(offchain));
+
+        // Add expected requests. Both uploads should be sent as they have different content types.
+        state.write().expect_request(testing::PendingRequest {
+            method: "POST".into(),
+            uri: "https://siasky.net/skynet/skyfile".into(),
+            body: REQUEST_BODY_TEXT.into(),
+            headers: vec![("Content-Type".to_owned(), CONTENT_TYPE_MULTIPART.to_owned())],
+            response: Some(RESPONSE_JSON.into()),
+            sent: true,
+            ..Default::default()
+        });
+        state.write().expect_request(testing::PendingRequest {
+            method: "POST".into(),
+            uri: "https://siasky.net/skynet/skyfile".into(),
+            body: REQUEST_BODY.into(),
+            headers: vec![("Content-Type".to_owned(), CONTENT_TYPE_MULTIPART.to_owned())],
+            response: Some(RESPONSE_JSON.into()),
+            sent: true,
+            ..Default::default()
+        });
+
+        t.execute_with(|| {
+            let cache = Default::default();
+            let text_opts = UploadOptions {
+                cache: Some(&cache),
+                content_type: Some("text/plain"),
+                ..Default::default()
+            };
+            let opts = UploadOptions {
+                cache: Some(&cache),
+                ..Default::default()
+            };
+
+            upload_bytes(DATA.as_bytes(), FILE_NAME, Some(&text_opts)).unwrap();
+            upload_bytes(DATA.as_bytes(), FILE_NAME, Some(&opts)).unwrap();
         })
     }
 }
@@ -1,11 +1,14 @@
 //! Utility functions.
 
-use serde::{Deserialize, Deserializer, Serializer};
-use sp_std::{str, vec::Vec};
+use serde::{de, Deserializer, Serializer};
+use sp_std::{fmt, str, vec::Vec};
 
 /// The Skynet URI protocol prefix.
 pub const URI_SKYNET_PREFIX: &str = "sia://";
 
+/// The Handshake URI protocol prefix.
+pub const URI_HANDSHAKE_PREFIX: &str = "hns://";
+
 pub fn concat_bytes(byte_slices: &[&[u8]]) -> Vec<u8> {
     let mut len = 0;
     for bytes in byte_slices {
@@ -14,8 +17,7 @@ pub fn concat_bytes(byte_slices: &[&[u8]]) -> Vec<u8> {
     let mut final_bytes = Vec::with_capacity(len);
 
     for bytes in byte_slices {
-        let mut v = bytes.to_vec();
-        final_bytes.append(&mut v);
+        final_bytes.extend_from_slice(bytes);
     }
 
     final_bytes
@@ -29,13 +31,72 @@ pub fn concat_strs(strs: &[&str]) -> Vec<u8> {
     let mut str_bytes = Vec::with_capacity(len);
 
     for s in strs {
-        let mut v = str_to_bytes(s);
-        str_bytes.append(&mut v);
+        str_bytes.extend_from_slice(s.as_bytes());
     }
 
     str_bytes
 }
 
+/// Percent-encodes `s` for use in a URL query or path component. Matches `encodeURIComponent` in
+/// JavaScript.
+pub fn encode_uri_component(s: &str) -> Vec<u8> {
+    const HEX_DIGITS: &[u8] = b"0123456789ABCDEF";
+
+    let mut encoded = Vec::with_capacity(s.len());
+    for byte in s.bytes() {
+        match byte {
+            b'A'..=b'Z'
+            | b'a'..=b'z'
+            | b'0'..=b'9'
+            | b'-'
+            | b'_'
+            | b'.'
+            | b'!'
+            | b'~'
+            | b'*'
+            | b'\''
+            | b'('
+            | b')' => encoded.push(byte),
+            _ => {
+                encoded.push(b'%');
+                encoded.push(HEX_DIGITS[(byte >> 4) as usize]);
+                encoded.push(HEX_DIGITS[(byte & 0x0f) as usize]);
+            }
+        }
+    }
+
+    encoded
+}
+
+/// Appends the query `params` to `url`. The values are percent-encoded.
+pub fn add_query_params(url: &str, params: &[(&str, &str)]) -> Vec<u8> {
+    let mut url_bytes = str_to_bytes(url);
+
+    for (i, (key, value)) in params.iter().enumerate() {
+        url_bytes.push(if i == 0 { b'?' } else { b'&' });
+        url_bytes.extend_from_slice(key.as_bytes());
+        url_bytes.push(b'=');
+        url_bytes.append(&mut encode_uri_component(value));
+    }
+
+    url_bytes
+}
+
+/// Formats `num` as a decimal string.
+pub fn format_number(mut num: u64) -> Vec<u8> {
+    let mut digits = Vec::new();
+    loop {
+        digits.push(b'0' + (num % 10) as u8);
+        num /= 10;
+        if num == 0 {
+            break;
+        }
+    }
+    digits.reverse();
+
+    digits
+}
+
 pub fn format_skylink(skylink: &[u8]) -> Vec<u8> {
     concat_bytes(&[&str_to_bytes(URI_SKYNET_PREFIX), skylink])
 }
@@ -100,12 +161,27 @@ pub fn trim_prefix<'a>(s: &'a str, prefix: &str) -> &'a str {
     }
 }
 
+/// Deserializes a string to bytes. The string is copied, as strings with escapes can't be
+/// borrowed from the input.
 pub fn de_string_to_bytes<'de, D>(de: D) -> Result<Vec<u8>, D::Error>
 where
     D: Deserializer<'de>,
 {
-    let s: &str = Deserialize::deserialize(de)?;
-    Ok(s.as_bytes().to_vec())
+    struct BytesVisitor;
+
+    impl<'de> de::Visitor<'de> for BytesVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a string")
+        }
+
+        fn visit_str<E: de::Error>(self, s: &str) -> Result<Vec<u8>, E> {
+            Ok(str_to_bytes(s))
+        }
+    }
+
+    de.deserialize_str(BytesVisitor)
 }
 
 pub fn ser_bytes_to_string<S>(v: &[u8], s: S) -> Result<S::Ok, S::Error>
@@ -122,6 +198,44 @@ mod tests {
 
     use sp_std::str;
 
+    #[test]
+    fn should_encode_uri_components() {
+        assert_eq!(encode_uri_component("foo"), str_to_bytes("foo"));
+        assert_eq!(
+            encode_uri_component("my dir/ü?&="),
+            str_to_bytes("my%20dir%2F%C3%BC%3F%26%3D")
+        );
+        assert_eq!(
+            encode_uri_component("a-b_c.d!~*'()"),
+            str_to_bytes("a-b_c.d!~*'()")
+        );
+    }
+
+    #[test]
+    fn should_add_query_params() {
+        assert_eq!(
+            add_query_params(DEFAULT_PORTAL_URL, &[]),
+            str_to_bytes(DEFAULT_PORTAL_URL)
+        );
+        assert_eq!(
+            add_query_params(
+                DEFAULT_PORTAL_URL,
+                &[("filename", "my dir"), ("tryfiles", "[\"index.html\"]")]
+            ),
+            str_to_bytes("https://siasky.net?filename=my%20dir&tryfiles=%5B%22index.html%22%5D")
+        );
+    }
+
+    #[test]
+    fn should_format_numbers() {
+        assert_eq!(format_number(0), str_to_bytes("0"));
+        assert_eq!(format_number(42), str_to_bytes("42"));
+        assert_eq!(
+            format_number(u64::MAX),
+            str_to_bytes("18446744073709551615")
+        );
+    }
+
     #[test]
     fn make_url_test() {
         const ENTRY_LINK: &str = "AQAZ1R-KcL4NO_xIVf0q8B1ngPVd6ec-Pu54O0Cto387Nw";